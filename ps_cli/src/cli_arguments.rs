@@ -1,5 +1,17 @@
 use clap::Parser;
 use clap::Subcommand;
+use clap::ValueEnum;
+
+use std::path::PathBuf;
+
+// Given when starting the CLI, before the interactive prompt.
+#[derive(Parser, Debug)]
+#[command(name = "ps_cli")]
+pub struct StartupArguments {
+    // Points file shared with the visualizer, overriding PS_POINTS_FILE and ps_config.toml
+    #[arg(long)]
+    pub points_file: Option<PathBuf>,
+}
 
 #[derive(Parser, Debug)]
 #[command(name = "ps_cli")]
@@ -22,6 +34,17 @@ pub enum CliCommand {
     Clear(ClearCommand),
     Corrode(CorrodeCommand),
     Relax(RelaxCommand),
+    Connect(ConnectCommand),
+    Load(LoadCommand),
+    Save(SaveCommand),
+    Stats(StatsCommand),
+    Info(InfoCommand),
+    Publish(PublishCommand),
+    Record(RecordCommand),
+    Replay(ReplayCommand),
+    ExportObj(ExportObjCommand),
+    SaveState(StateCommand),
+    LoadState(StateCommand),
 }
 
 
@@ -40,10 +63,35 @@ pub enum CreateCommand {
     
         #[arg(long, default_value = "0.03")]
         step: f32,
+    },
+    FromStl {
+        #[arg(long)]
+        path: String,
+
+        #[arg(long, value_enum, default_value = "surface")]
+        mode: StlSamplingMode,
+
+        // Surface mode: points per unit area
+        #[arg(long, default_value = "1000")]
+        density: f64,
+
+        // Volume mode: grid step
+        #[arg(long, default_value = "0.03")]
+        step: f64,
+
+        #[arg(long, default_value = "0")]
+        seed: u64,
     }
 }
 
 
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum StlSamplingMode {
+    Surface,
+    Volume,
+}
+
+
 #[derive(Parser, Debug)]
 pub struct ClearCommand {
 
@@ -62,3 +110,142 @@ pub struct RelaxCommand {
     #[arg(long, default_value = "3")]
     iterations: usize,
 }
+
+
+// Rebuilds the connections between points.
+#[derive(Parser, Debug)]
+pub struct ConnectCommand {
+    #[arg(long, value_enum, default_value = "radius")]
+    pub mode: ConnectionModeArgument,
+
+    // Radius and knn-within-radius modes
+    #[arg(long, default_value = "0.05")]
+    pub radius: f64,
+
+    // Neighbours per point, for the knn modes
+    #[arg(long, default_value = "8")]
+    pub k: usize,
+}
+
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ConnectionModeArgument {
+    Radius,
+    Knn,
+    MutualKnn,
+    KnnWithinRadius,
+}
+
+
+#[derive(Parser, Debug)]
+pub struct LoadCommand {
+    // Format chosen from the extension (.bin, .ply, .xyz, .csv, .las, .pcd, .npy, .npz, .obj)
+    #[arg(long)]
+    pub path: String,
+
+    // Text formats only: column mapping, e.g. x,y,z,r,g,b,nx,ny,nz,intensity (skip to ignore a column)
+    #[arg(long)]
+    pub columns: Option<String>,
+
+    // Text formats only: lines to skip at the start of the file
+    #[arg(long, default_value = "0")]
+    pub skip_lines: usize,
+}
+
+
+#[derive(Parser, Debug)]
+pub struct SaveCommand {
+    // Format chosen from the extension (.bin, .ply, .xyz, .csv, .las, .pcd, .npy, .npz, .obj)
+    #[arg(long)]
+    pub path: String,
+
+    // PLY and PCD only: writing text instead of binary
+    #[arg(long)]
+    pub ascii: bool,
+
+    // Text formats only: decimals written for each value
+    #[arg(long)]
+    pub precision: Option<usize>,
+
+    // .bin only: compressing with this quantization step, relative to the bounding box (e.g. 1e-5)
+    #[arg(long)]
+    pub quantize: Option<f64>,
+
+    // Keeping the exact f64 positions in extra channels, so that loading the file back is lossless
+    #[arg(long)]
+    pub exact: bool,
+}
+
+
+// Writes the mesh as a Wavefront OBJ file, for external viewers.
+#[derive(Parser, Debug)]
+pub struct ExportObjCommand {
+    #[arg(long, default_value = "maps/mesh.obj")]
+    pub path: String,
+
+    // Writing the connections between points as line elements
+    #[arg(long)]
+    pub connections: bool,
+}
+
+
+// Writes the mesh to the shared points file watched by the visualizer.
+#[derive(Parser, Debug)]
+pub struct PublishCommand {
+
+}
+
+
+// Prints the provenance of a saved file, or of the current mesh without a path.
+#[derive(Parser, Debug)]
+pub struct InfoCommand {
+    #[arg(long)]
+    pub path: Option<String>,
+}
+
+
+#[derive(Parser, Debug)]
+pub struct StatsCommand {
+    // Points file (.bin), read in a single pass without loading it whole
+    #[arg(long)]
+    pub path: String,
+
+    #[arg(long, default_value = "1000000")]
+    pub chunk_size: usize,
+}
+
+
+#[derive(Parser, Debug)]
+pub struct StateCommand {
+    // Mesh file holding the whole simulation state: positions, energies, breaking points, connections
+    #[arg(long, default_value = "maps/state.psm")]
+    pub path: String,
+}
+
+
+// Appends the mesh as a new frame of a timeline file.
+#[derive(Parser, Debug)]
+pub struct RecordCommand {
+    #[arg(long, default_value = "maps/timeline.pstl")]
+    pub path: String,
+
+    // Starting a new timeline instead of appending
+    #[arg(long)]
+    pub new: bool,
+}
+
+
+// Publishes timeline frames to the points file watched by the visualizer.
+#[derive(Parser, Debug)]
+pub struct ReplayCommand {
+    #[arg(long, default_value = "maps/timeline.pstl")]
+    pub path: String,
+
+    // Single frame to publish, all of them in order otherwise
+    #[arg(long)]
+    pub frame: Option<usize>,
+
+    // Delay between two frames
+    #[arg(long, default_value = "500")]
+    pub interval_ms: u64,
+}
@@ -1,15 +1,25 @@
 use ps_mesh::point_mesh::PointsMesh as PointsMesh;
+use ps_mesh::point_mesh::ConnectionMode;
+use ps_mesh::obj_export::ObjExportOptions;
+use ps_mesh::cloud_conversion::POSITION_CHANNELS;
 use ps_mesh::ps_creation::*;
+use ps_mesh::stl_sampling::{read_stl, sample_surface, fill_volume};
+use ps_data_layer::{CompressionOptions, Error, FileFormat, Metadata, PointChunks, PointCloud};
+use ps_data_layer::metadata;
+use ps_data_layer::{TimelineOptions, TimelineReader, TimelineWriter};
+use ps_data_layer::ply::PlyFormat;
+use ps_data_layer::xyz::{self, XyzOptions};
+use ps_data_layer::pcd::{self, PcdFormat};
 
 mod cli_arguments;
 use cli_arguments::*;
 
 // For the CLI
 use clap::Parser;
-use clap::Subcommand;
 use rustyline::{error::ReadlineError, Editor};
 
 // For the real-time visualization
+use std::path::Path;
 use std::sync::Arc;
 use warp::Filter;
 use tokio::sync::Mutex;
@@ -18,12 +28,20 @@ use tokio::sync::Mutex;
 mod server;
 use server::handle_ws_connection;
 
-// Alias
-type Point3D = nalgebra::Point3<f64>;
-
 #[tokio::main]
 async fn main() {
-    
+    let startup_arguments = StartupArguments::parse();
+    let points_file = match ps_data_layer::config::resolve_points_file(startup_arguments.points_file.as_deref()) {
+        Ok(working_file) => {
+            println!("Working file {} (from {:?})", working_file.path.display(), working_file.source);
+            working_file.path
+        }
+        Err(e) => {
+            eprintln!("Error: cannot resolve the working file: {}", e);
+            return;
+        }
+    };
+
     // shared space of point cloud
     let points_mesh: Arc<Mutex<PointsMesh>> = Arc::new(Mutex::new(PointsMesh::new()));
 
@@ -45,6 +63,9 @@ async fn main() {
     println!("Initializing the interactive CLI...");
     let mut rl = Editor::<()>::new();
 
+    // Provenance of the current mesh, saved along with it
+    let mut session_metadata = Metadata::new(env!("CARGO_PKG_VERSION"));
+
     // Defining the rustyline interactive CLI 
     loop {
         let readline = rl.readline("ps-cli> ");
@@ -67,19 +88,137 @@ async fn main() {
                             match create_command {
                                 CreateCommand::Cube { side, step } => {
                                     let mut mesh = points_mesh.lock().await;
-                                    create_cube(side, step,  &mut *mesh);
+                                    create_cube(side, step,  &mut mesh);
+                                    session_metadata.record(line.trim());
                                 },
                                 CreateCommand::Sphere { radius, step } => {
                                     let mut mesh = points_mesh.lock().await;
-                                    create_sphere(radius, step,  &mut *mesh);
+                                    create_sphere(radius, step,  &mut mesh);
+                                    session_metadata.record(line.trim());
+                                },
+                                CreateCommand::FromStl { path, mode, density, step, seed } => {
+                                    let triangles = match read_stl(Path::new(&path)) {
+                                        Ok(triangles) => triangles,
+                                        Err(e) => {
+                                            eprintln!("Error: cannot read {}: {}", path, e);
+                                            continue;
+                                        }
+                                    };
+                                    let mut mesh = points_mesh.lock().await;
+                                    let sampled = match mode {
+                                        StlSamplingMode::Surface => sample_surface(&triangles, density, seed, &mut mesh),
+                                        StlSamplingMode::Volume => fill_volume(&triangles, step, &mut mesh),
+                                    };
+                                    if let Err(e) = sampled {
+                                        eprintln!("Error: cannot sample {}: {}", path, e);
+                                        continue;
+                                    }
+                                    session_metadata.seed = Some(seed);
+                                    session_metadata.record(line.trim());
                                 }
                             },
                         CliCommand::Clear(_) => {
                             let mut mesh = points_mesh.lock().await;
                             mesh.points.clear();
+                            mesh.invalidate_spatial_index();
+                            session_metadata.record(line.trim());
+                        }
+                        CliCommand::Corrode(_corrode_command) => continue,//corrode(corrode_command.iterations),
+                        CliCommand::Relax(_relax_command) => continue,//relax(relax_command.iterations),
+                        CliCommand::Connect(connect_command) => {
+                            let mode = match connect_command.mode {
+                                ConnectionModeArgument::Radius => ConnectionMode::Radius(connect_command.radius),
+                                ConnectionModeArgument::Knn => ConnectionMode::Knn(connect_command.k),
+                                ConnectionModeArgument::MutualKnn => ConnectionMode::MutualKnn(connect_command.k),
+                                ConnectionModeArgument::KnnWithinRadius => ConnectionMode::KnnWithinRadius { k: connect_command.k, radius: connect_command.radius },
+                            };
+                            let mut mesh = points_mesh.lock().await;
+                            mesh.connect(mode);
+                            let (average_connections, unconnected_points) = mesh.get_statistics();
+                            println!("{:.2} connections per point, {} unconnected points", average_connections, unconnected_points);
+                            session_metadata.record(line.trim());
+                        }
+                        CliCommand::Load(load_command) => {
+                            match load_cloud(&load_command) {
+                                Ok(cloud) => match PointsMesh::try_from(&cloud) {
+                                    Ok(loaded) => {
+                                        println!("Loaded {} points from {}", loaded.points.len(), load_command.path);
+                                        *points_mesh.lock().await = loaded;
+                                        session_metadata = inherited_metadata(cloud.metadata, Path::new(&load_command.path));
+                                        session_metadata.record(line.trim());
+                                    }
+                                    Err(e) => eprintln!("Error: cannot convert {}: {}", load_command.path, e),
+                                },
+                                Err(e) => eprintln!("Error: cannot load {}: {}", load_command.path, e),
+                            }
+                        }
+                        CliCommand::Save(save_command) => {
+                            let mut cloud = match save_command.exact {
+                                true => points_mesh.lock().await.to_cloud_exact(),
+                                false => PointCloud::from(&*points_mesh.lock().await),
+                            };
+                            cloud.metadata = Some(session_metadata.clone());
+                            match save_cloud(&cloud, &save_command) {
+                                Ok(count) => println!("Saved {} points to {}", count, save_command.path),
+                                Err(e) => eprintln!("Error: cannot save {}: {}", save_command.path, e),
+                            }
+                        }
+                        CliCommand::ExportObj(export_command) => {
+                            let options = ObjExportOptions { connections: export_command.connections };
+                            match points_mesh.lock().await.write_obj(Path::new(&export_command.path), &options) {
+                                Ok(count) => println!("Exported {} points to {}", count, export_command.path),
+                                Err(e) => eprintln!("Error: cannot export {}: {}", export_command.path, e),
+                            }
+                        }
+                        CliCommand::SaveState(state_command) => {
+                            let mesh = points_mesh.lock().await;
+                            if let Err(e) = mesh.write_to_file(Path::new(&state_command.path)) {
+                                eprintln!("Error: cannot save {}: {}", state_command.path, e);
+                            }
+                        }
+                        CliCommand::LoadState(state_command) => {
+                            match PointsMesh::new_from_file(Path::new(&state_command.path)) {
+                                Ok(loaded) => {
+                                    println!("Loaded {} points from {}", loaded.points.len(), state_command.path);
+                                    *points_mesh.lock().await = loaded;
+                                    session_metadata = inherited_metadata(None, Path::new(&state_command.path));
+                                    session_metadata.record(line.trim());
+                                }
+                                Err(e) => eprintln!("Error: cannot load {}: {}", state_command.path, e),
+                            }
+                        }
+                        CliCommand::Publish(_) => {
+                            let mut cloud = PointCloud::from(&*points_mesh.lock().await);
+                            cloud.metadata = Some(session_metadata.clone());
+                            if let Err(e) = cloud.write_to_file(&points_file) {
+                                eprintln!("Error: cannot publish to {}: {}", points_file.display(), e);
+                            }
+                        }
+                        CliCommand::Record(record_command) => {
+                            let cloud = PointCloud::from(&*points_mesh.lock().await);
+                            match record_frame(&cloud, &record_command) {
+                                Ok(frame) => println!("Recorded frame {} to {}", frame, record_command.path),
+                                Err(e) => eprintln!("Error: cannot record to {}: {}", record_command.path, e),
+                            }
+                        }
+                        CliCommand::Replay(replay_command) => {
+                            if let Err(e) = replay_frames(&replay_command, &points_file).await {
+                                eprintln!("Error: cannot replay {}: {}", replay_command.path, e);
+                            }
+                        }
+                        CliCommand::Info(info_command) => match &info_command.path {
+                            Some(path) => match metadata::read_sidecar(Path::new(path)) {
+                                Ok(Some(file_metadata)) => print_metadata(&file_metadata),
+                                Ok(None) => println!("No metadata for {}", path),
+                                Err(e) => eprintln!("Error: cannot read the metadata of {}: {}", path, e),
+                            },
+                            None => print_metadata(&session_metadata),
+                        },
+                        CliCommand::Stats(stats_command) => {
+                            if let Err(e) = print_stats(&stats_command) {
+                                eprintln!("Error: cannot read {}: {}", stats_command.path, e);
+                            }
                         }
-                        CliCommand::Corrode(corrode_command) => continue,//corrode(corrode_command.iterations),
-                        CliCommand::Relax(relax_command) => continue,//relax(relax_command.iterations),
                     }
                 }
 
@@ -100,6 +239,131 @@ async fn main() {
     }
 }
 
+fn load_cloud(command: &LoadCommand) -> ps_data_layer::Result<PointCloud> {
+    let path = Path::new(&command.path);
+    let text_options = match FileFormat::from_path(path) {
+        Some(FileFormat::Xyz) => Some(XyzOptions::default()),
+        Some(FileFormat::Csv) => Some(XyzOptions::csv()),
+        _ => None,
+    };
+
+    match text_options {
+        Some(mut options) => {
+            options.skip_lines = command.skip_lines;
+            match &command.columns {
+                Some(columns) => options.columns = xyz::parse_columns(columns)?,
+                // As written by save, the header line names the columns.
+                None => options = options.with_header_columns(path)?,
+            }
+            xyz::read_xyz(path, &options)
+        }
+        None => PointCloud::load(path),
+    }
+}
+
+fn save_cloud(cloud: &PointCloud, command: &SaveCommand) -> ps_data_layer::Result<usize> {
+    let path = Path::new(&command.path);
+    let count = match (FileFormat::from_path(path), command.quantize) {
+        (Some(FileFormat::Points), Some(step)) => {
+            // Quantized positions cannot narrow from exact ones, the channels would only take room.
+            let mut quantized = cloud.clone();
+            for name in POSITION_CHANNELS {
+                quantized.remove_attribute(name);
+            }
+            let report = quantized.write_compressed(path, &CompressionOptions { relative_step: step })?;
+            println!("Max reconstruction error {} (step {})", report.max_error, report.step);
+            return Ok(report.points);
+        }
+        (_, Some(_)) => return Err(Error::InvalidInput("--quantize only applies to .bin files".to_string())),
+        (Some(FileFormat::Ply), _) if command.ascii => cloud.save_ply(path, PlyFormat::Ascii)?,
+        (Some(FileFormat::Pcd), _) if command.ascii => pcd::write_pcd(path, cloud, PcdFormat::Ascii)?,
+        (Some(FileFormat::Xyz), _) if command.precision.is_some() => xyz::write_xyz(path, cloud,
+            &XyzOptions { precision: command.precision, ..XyzOptions::default() }.with_columns_for(cloud))?,
+        (Some(FileFormat::Csv), _) if command.precision.is_some() => xyz::write_xyz(path, cloud,
+            &XyzOptions { precision: command.precision, skip_lines: 1, ..XyzOptions::csv() }.with_columns_for(cloud))?,
+        _ => return cloud.save(path),
+    };
+    cloud.write_metadata(path)?;
+    Ok(count)
+}
+
+// Continuing the history of a loaded file, which becomes the parent of what is saved next.
+fn inherited_metadata(loaded: Option<Metadata>, path: &Path) -> Metadata {
+    let mut inherited = loaded.unwrap_or_else(|| Metadata::new(env!("CARGO_PKG_VERSION")));
+    inherited.tool_version = env!("CARGO_PKG_VERSION").to_string();
+    inherited.parent_hash = metadata::file_hash(path).ok();
+    inherited.content_hash = None;
+    inherited.saved = None;
+    inherited
+}
+
+fn print_metadata(metadata: &Metadata) {
+    println!("Tool version  {}", metadata.tool_version);
+    println!("Created       {}", metadata.created);
+    println!("Saved         {}", metadata.saved.as_deref().unwrap_or("-"));
+    println!("Seed          {}", metadata.seed.map(|seed| seed.to_string()).unwrap_or("-".to_string()));
+    println!("Parent hash   {}", metadata.parent_hash.as_deref().unwrap_or("-"));
+    println!("Content hash  {}", metadata.content_hash.as_deref().unwrap_or("-"));
+    println!("Commands:");
+    for record in &metadata.commands {
+        println!("  {}  {}", record.time, record.command);
+    }
+}
+
+fn record_frame(cloud: &PointCloud, command: &RecordCommand) -> ps_data_layer::Result<usize> {
+    let path = Path::new(&command.path);
+    let mut writer = match command.new {
+        true => TimelineWriter::create(path, TimelineOptions::default())?,
+        false => TimelineWriter::append(path, TimelineOptions::default())?,
+    };
+    writer.push(cloud)
+}
+
+async fn replay_frames(command: &ReplayCommand, points_file: &Path) -> ps_data_layer::Result<()> {
+    let mut reader = TimelineReader::open(Path::new(&command.path))?;
+    let frames = match command.frame {
+        Some(frame) => frame..frame.checked_add(1)
+            .ok_or_else(|| Error::InvalidInput(format!("frame {} out of {} frames", frame, reader.len())))?,
+        None => 0..reader.len(),
+    };
+
+    for frame in frames {
+        let cloud = reader.read_frame(frame)?;
+        cloud.write_to_file(points_file)?;
+        println!("Frame {} of {}", frame, reader.len());
+        if command.frame.is_none() {
+            tokio::time::sleep(std::time::Duration::from_millis(command.interval_ms)).await;
+        }
+    }
+    Ok(())
+}
+
+// Bounding box and centroid, streaming the file chunk by chunk.
+fn print_stats(command: &StatsCommand) -> ps_data_layer::Result<()> {
+    let chunks = PointChunks::open(Path::new(&command.path), command.chunk_size)?;
+    let count = chunks.len();
+
+    let mut min = nalgebra::Point3::from([f64::INFINITY; 3]);
+    let mut max = nalgebra::Point3::from([f64::NEG_INFINITY; 3]);
+    let mut sum = nalgebra::Vector3::<f64>::zeros();
+    for chunk in chunks {
+        for point in chunk? {
+            let point = nalgebra::Point3::new(point.x as f64, point.y as f64, point.z as f64);
+            min = min.inf(&point);
+            max = max.sup(&point);
+            sum += point.coords;
+        }
+    }
+
+    println!("{} points", count);
+    if count > 0 {
+        println!("  min      {:?}", min.coords.as_slice());
+        println!("  max      {:?}", max.coords.as_slice());
+        println!("  centroid {:?}", (sum / count as f64).as_slice());
+    }
+    Ok(())
+}
+
 async fn run_server(point_cloud: Arc<Mutex<PointsMesh>>, clients: Arc<Mutex<Vec<Arc<Mutex<warp::ws::WebSocket>>>>>) {
     // Serve the static files
     let static_files = warp::fs::dir("./static");
@@ -119,4 +383,32 @@ async fn run_server(point_cloud: Arc<Mutex<PointsMesh>>, clients: Arc<Mutex<Vec<
     let routes = static_files.or(ws_route);
     println!("Serving on 127.0.0.1:3030...");
     warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ps_data_layer::{AttributeData, Point3};
+
+    #[test]
+    fn csv_save_load_test() {
+        let mut cloud = PointCloud::new(vec![Point3::new(0.5, -1., 2.), Point3::new(3., 4., 5.25)]);
+        cloud.set_attribute("color", AttributeData::Color(vec![[255, 128, 0], [1, 2, 3]])).unwrap();
+        cloud.set_attribute("normal", AttributeData::Normal(vec![[0., 0., 1.], [0., 1., 0.]])).unwrap();
+        cloud.set_attribute("intensity", AttributeData::ScalarF32(vec![0.5, 0.75])).unwrap();
+
+        let path = std::env::temp_dir().join("ps_cli_round_trip.csv").to_string_lossy().to_string();
+        let save_command = SaveCommand { path: path.clone(), ascii: false, precision: None, quantize: None, exact: false };
+        assert_eq!(2, save_cloud(&cloud, &save_command).expect("Saving failed"));
+
+        let load_command = LoadCommand { path, columns: None, skip_lines: 0 };
+        let loaded = load_cloud(&load_command).expect("Loading failed");
+        assert_eq!(cloud.points, loaded.points);
+        assert_eq!(cloud.attributes, loaded.attributes);
+
+        // Quantizing only exists for .bin files.
+        let quantize_command = SaveCommand { quantize: Some(1e-3), ..save_command };
+        assert!(matches!(save_cloud(&cloud, &quantize_command), Err(Error::InvalidInput(_))));
+    }
+}
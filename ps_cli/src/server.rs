@@ -3,7 +3,6 @@ use ps_mesh::point_mesh::PointsMesh;
 // For the real-time visualization  
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use serde_json::json;
 use tokio::time::{Duration, Instant, interval_at};
 use futures_util::SinkExt;
 
@@ -36,6 +35,10 @@ pub async fn handle_ws_connection(ws: warp::ws::WebSocket, point_cloud: Arc<Mute
 }
 
 
+// Positions as [[x, y, z], ...]
 pub fn points_mesh_to_json(points_mesh: &PointsMesh) -> String {
-    json!(points_mesh.get_points_for_display()).to_string()
+    serde_json::to_string(&points_mesh.points).unwrap_or_else(|e| {
+        eprintln!("Error serializing the points: {}", e);
+        "[]".to_string()
+    })
 }
\ No newline at end of file
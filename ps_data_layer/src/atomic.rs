@@ -0,0 +1,127 @@
+// Crash-safe writes.
+// The content goes to a temporary file in the target folder, which is renamed over
+// the target once complete: readers watching the target see either the previous
+// file or the new one, never a partial write.
+// On Windows the rename fails while another handle maps the target (see MappedPoints).
+
+use crate::error::{Error, Result};
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+// Numbering the temporary files, for concurrent writes from the same process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    // Flushing the file and its folder to disk before returning, so the new content
+    // also survives a power loss. Slower, off by default.
+    pub fsync: bool,
+}
+
+
+pub fn write_atomically<T, F>(file_path: &Path, options: &WriteOptions, write: F) -> Result<T>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<T>,
+{
+    let (folder, temp_path) = temp_path_for(file_path)?;
+    std::fs::create_dir_all(&folder)?;
+
+    let result = write_temp_file(&temp_path, options, write)
+        .and_then(|value| {
+            std::fs::rename(&temp_path, file_path)?;
+            Ok(value)
+        });
+
+    match result {
+        Ok(value) => {
+            if options.fsync {
+                sync_folder(&folder)?;
+            }
+            Ok(value)
+        }
+        Err(error) => {
+            let _ = std::fs::remove_file(&temp_path);
+            Err(error)
+        }
+    }
+}
+
+
+fn write_temp_file<T, F>(temp_path: &Path, options: &WriteOptions, write: F) -> Result<T>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<T>,
+{
+    let mut writer = BufWriter::new(File::create(temp_path)?);
+    let value = write(&mut writer)?;
+    writer.flush()?;
+
+    if options.fsync {
+        writer.get_ref().sync_all()?;
+    }
+    Ok(value)
+}
+
+
+// Hidden file next to the target, unique per process and per call.
+fn temp_path_for(file_path: &Path) -> Result<(PathBuf, PathBuf)> {
+    let invalid_path = |reason: &str| Error::InvalidPath {
+        path: file_path.to_path_buf(),
+        reason: reason.to_string(),
+    };
+
+    let file_name = file_path.file_name().ok_or_else(|| invalid_path("no file name"))?;
+    let folder = file_path.parent().ok_or_else(|| invalid_path("no parent folder"))?;
+
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.{}.tmp", std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    Ok((folder.to_path_buf(), folder.join(temp_name)))
+}
+
+
+// Persisting the rename itself. Folders cannot be opened for syncing on every platform.
+fn sync_folder(folder: &Path) -> Result<()> {
+    if cfg!(unix) {
+        let folder = if folder.as_os_str().is_empty() { Path::new(".") } else { folder };
+        File::open(folder)?.sync_all()?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_write_test() {
+        let folder = std::env::temp_dir().join("ps_data_layer_atomic");
+        let test_path = folder.join("points.bin");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(&test_path, b"previous").unwrap();
+
+        // A failing write leaves the previous content and no temporary file.
+        let result: Result<()> = write_atomically(&test_path, &WriteOptions::default(), |writer| {
+            writer.write_all(b"partial")?;
+            Err(Error::InvalidInput("failure halfway".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(b"previous".to_vec(), std::fs::read(&test_path).unwrap());
+        assert_eq!(1, std::fs::read_dir(&folder).unwrap().count(), "Temporary file left behind");
+
+        // A complete one replaces it.
+        let options = WriteOptions { fsync: true };
+        write_atomically(&test_path, &options, |writer| Ok(writer.write_all(b"new")?)).unwrap();
+        assert_eq!(b"new".to_vec(), std::fs::read(&test_path).unwrap());
+        assert_eq!(1, std::fs::read_dir(&folder).unwrap().count(), "Temporary file left behind");
+
+        // Two writes to the same target never share a temporary file.
+        assert_ne!(temp_path_for(&test_path).unwrap().1, temp_path_for(&test_path).unwrap().1);
+    }
+}
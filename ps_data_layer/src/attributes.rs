@@ -0,0 +1,299 @@
+// Per-point attribute channels.
+// Each channel has a name and a typed vector holding one value per point.
+// On disk the channels follow the positions block:
+//   channel count   u32
+//   for each channel:
+//     kind          u8       see AttributeKind
+//     name length   u16
+//     name          utf-8 bytes
+//     values        point count values, little endian
+
+use std::io::Read;
+use std::io::Write;
+use crate::error::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeKind {
+    Color,
+    Normal,
+    ScalarF32,
+    ScalarF64,
+    Label,
+}
+
+impl AttributeKind {
+    pub fn from_code(code: u8) -> Option<AttributeKind> {
+        match code {
+            0 => Some(AttributeKind::Color),
+            1 => Some(AttributeKind::Normal),
+            2 => Some(AttributeKind::ScalarF32),
+            3 => Some(AttributeKind::ScalarF64),
+            4 => Some(AttributeKind::Label),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            AttributeKind::Color => 0,
+            AttributeKind::Normal => 1,
+            AttributeKind::ScalarF32 => 2,
+            AttributeKind::ScalarF64 => 3,
+            AttributeKind::Label => 4,
+        }
+    }
+
+    // Bit of this kind in the header attributes bitmask.
+    pub fn bit(&self) -> u32 {
+        1 << self.code()
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AttributeData {
+    Color(Vec<[u8; 3]>),
+    Normal(Vec<[f32; 3]>),
+    ScalarF32(Vec<f32>),
+    ScalarF64(Vec<f64>),
+    Label(Vec<u32>),
+}
+
+impl AttributeData {
+    pub fn kind(&self) -> AttributeKind {
+        match self {
+            AttributeData::Color(_) => AttributeKind::Color,
+            AttributeData::Normal(_) => AttributeKind::Normal,
+            AttributeData::ScalarF32(_) => AttributeKind::ScalarF32,
+            AttributeData::ScalarF64(_) => AttributeKind::ScalarF64,
+            AttributeData::Label(_) => AttributeKind::Label,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            AttributeData::Color(values) => values.len(),
+            AttributeData::Normal(values) => values.len(),
+            AttributeData::ScalarF32(values) => values.len(),
+            AttributeData::ScalarF64(values) => values.len(),
+            AttributeData::Label(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Values at the given indices, in that order.
+    pub fn select(&self, indices: &[usize]) -> AttributeData {
+        fn pick<T: Copy>(values: &[T], indices: &[usize]) -> Vec<T> {
+            indices.iter().map(|&index| values[index]).collect()
+        }
+        match self {
+            AttributeData::Color(values) => AttributeData::Color(pick(values, indices)),
+            AttributeData::Normal(values) => AttributeData::Normal(pick(values, indices)),
+            AttributeData::ScalarF32(values) => AttributeData::ScalarF32(pick(values, indices)),
+            AttributeData::ScalarF64(values) => AttributeData::ScalarF64(pick(values, indices)),
+            AttributeData::Label(values) => AttributeData::Label(pick(values, indices)),
+        }
+    }
+
+    fn write_values<W: Write>(&self, writer: &mut W) -> Result<()> {
+        match self {
+            AttributeData::Color(values) => for value in values {
+                writer.write_all(value)?;
+            },
+            AttributeData::Normal(values) => for value in values {
+                for component in value {
+                    writer.write_all(&component.to_le_bytes())?;
+                }
+            },
+            AttributeData::ScalarF32(values) => for value in values {
+                writer.write_all(&value.to_le_bytes())?;
+            },
+            AttributeData::ScalarF64(values) => for value in values {
+                writer.write_all(&value.to_le_bytes())?;
+            },
+            AttributeData::Label(values) => for value in values {
+                writer.write_all(&value.to_le_bytes())?;
+            },
+        }
+        Ok(())
+    }
+
+    // The count comes from the file: nothing is reserved upfront, a short file fails on
+    // read_exact before growing past its own size.
+    fn read_values<R: Read>(reader: &mut R, kind: AttributeKind, count: usize) -> Result<AttributeData> {
+        let data = match kind {
+            AttributeKind::Color => {
+                let mut values = Vec::new();
+                let mut buffer = [0u8; 3];
+                for _ in 0..count {
+                    reader.read_exact(&mut buffer)?;
+                    values.push(buffer);
+                }
+                AttributeData::Color(values)
+            }
+            AttributeKind::Normal => {
+                let mut values = Vec::new();
+                let mut buffer = [0u8; 12];
+                for _ in 0..count {
+                    reader.read_exact(&mut buffer)?;
+                    values.push([
+                        f32::from_le_bytes(buffer[0..4].try_into().unwrap()),
+                        f32::from_le_bytes(buffer[4..8].try_into().unwrap()),
+                        f32::from_le_bytes(buffer[8..12].try_into().unwrap())]);
+                }
+                AttributeData::Normal(values)
+            }
+            AttributeKind::ScalarF32 => {
+                let mut values = Vec::new();
+                let mut buffer = [0u8; 4];
+                for _ in 0..count {
+                    reader.read_exact(&mut buffer)?;
+                    values.push(f32::from_le_bytes(buffer));
+                }
+                AttributeData::ScalarF32(values)
+            }
+            AttributeKind::ScalarF64 => {
+                let mut values = Vec::new();
+                let mut buffer = [0u8; 8];
+                for _ in 0..count {
+                    reader.read_exact(&mut buffer)?;
+                    values.push(f64::from_le_bytes(buffer));
+                }
+                AttributeData::ScalarF64(values)
+            }
+            AttributeKind::Label => {
+                let mut values = Vec::new();
+                let mut buffer = [0u8; 4];
+                for _ in 0..count {
+                    reader.read_exact(&mut buffer)?;
+                    values.push(u32::from_le_bytes(buffer));
+                }
+                AttributeData::Label(values)
+            }
+        };
+        Ok(data)
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Attribute {
+    pub name: String,
+    pub data: AttributeData,
+}
+
+impl Attribute {
+    pub fn new(i_name: &str, i_data: AttributeData) -> Attribute {
+        Attribute { name: i_name.to_string(), data: i_data }
+    }
+}
+
+
+// Bitmask of the kinds present in the given channels, as stored in the header.
+pub fn attributes_bitmask(attributes: &[Attribute]) -> u32 {
+    attributes.iter().fold(0, |mask, attribute| mask | attribute.data.kind().bit())
+}
+
+
+pub fn write_attributes<W: Write>(writer: &mut W, attributes: &[Attribute]) -> Result<()> {
+    writer.write_all(&(attributes.len() as u32).to_le_bytes())?;
+    for attribute in attributes {
+        let name = attribute.name.as_bytes();
+        if name.len() > u16::MAX as usize {
+            return Err(Error::InvalidInput("attribute name too long".to_string()));
+        }
+        writer.write_all(&[attribute.data.kind().code()])?;
+        writer.write_all(&(name.len() as u16).to_le_bytes())?;
+        writer.write_all(name)?;
+        attribute.data.write_values(writer)?;
+    }
+    Ok(())
+}
+
+
+pub fn read_attributes<R: Read>(reader: &mut R, point_count: usize) -> Result<Vec<Attribute>> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    let channels = u32::from_le_bytes(buffer);
+
+    let mut attributes = Vec::new();
+    for _ in 0..channels {
+        let mut kind = [0u8; 1];
+        reader.read_exact(&mut kind)?;
+        let kind = AttributeKind::from_code(kind[0])
+            .ok_or_else(|| Error::Malformed(format!("unknown attribute kind {}", kind[0])))?;
+
+        let mut name_length = [0u8; 2];
+        reader.read_exact(&mut name_length)?;
+        let mut name = vec![0u8; u16::from_le_bytes(name_length) as usize];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name)
+            .map_err(|_| Error::Malformed("attribute name is not valid utf-8".to_string()))?;
+
+        let data = AttributeData::read_values(reader, kind, point_count)?;
+        attributes.push(Attribute { name, data });
+    }
+    Ok(attributes)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_kinds() -> Vec<Attribute> {
+        vec![
+            Attribute::new("color", AttributeData::Color(vec![[255, 0, 1], [2, 3, 4]])),
+            Attribute::new("normal", AttributeData::Normal(vec![[0., 0., 1.], [-1., 0.5, 0.]])),
+            Attribute::new("intensité", AttributeData::ScalarF32(vec![0.25, f32::MAX])),
+            Attribute::new("energy", AttributeData::ScalarF64(vec![1e-300, -2.])),
+            Attribute::new("", AttributeData::Label(vec![0, u32::MAX])),
+        ]
+    }
+
+    #[test]
+    fn attributes_round_trip_test() {
+        let attributes = all_kinds();
+        let mut bytes = Vec::new();
+        write_attributes(&mut bytes, &attributes).expect("Writing failed");
+        bytes.push(7);
+
+        let mut reader = &bytes[..];
+        assert_eq!(attributes, read_attributes(&mut reader, 2).expect("Reading failed"));
+        assert_eq!([7], reader);
+        assert_eq!(0b11111, attributes_bitmask(&attributes));
+
+        let mut bytes = Vec::new();
+        write_attributes(&mut bytes, &[]).unwrap();
+        assert!(read_attributes(&mut &bytes[..], 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn attributes_rejection_test() {
+        let attribute = Attribute::new("label", AttributeData::Label(vec![1, 2]));
+        let mut bytes = Vec::new();
+        write_attributes(&mut bytes, &[attribute]).unwrap();
+
+        // Truncated channel, and a point count larger than the channel.
+        assert!(matches!(read_attributes(&mut &bytes[..bytes.len() - 1], 2), Err(Error::Io(_))));
+        assert!(matches!(read_attributes(&mut &bytes[..], 3), Err(Error::Io(_))));
+
+        // Unknown kind and a name that is not utf-8.
+        let mut corrupt = bytes.clone();
+        corrupt[4] = 42;
+        assert!(matches!(read_attributes(&mut &corrupt[..], 2), Err(Error::Malformed(_))));
+        let mut corrupt = bytes.clone();
+        corrupt[7] = 0xFF;
+        assert!(matches!(read_attributes(&mut &corrupt[..], 2), Err(Error::Malformed(_))));
+
+        let long_name = "n".repeat(u16::MAX as usize + 1);
+        let attribute = Attribute::new(&long_name, AttributeData::Label(Vec::new()));
+        assert!(matches!(write_attributes(&mut Vec::new(), &[attribute]), Err(Error::InvalidInput(_))));
+    }
+}
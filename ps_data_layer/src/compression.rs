@@ -0,0 +1,298 @@
+// Quantized encoding of the positions block, for large points files.
+// Positions are snapped to a grid anchored at the bounding box minimum, with a step
+// relative to the largest bounding box extent. The grid coordinates are interleaved
+// in Morton codes, sorted, delta coded as LEB128 varints and deflated.
+// Layout of the positions block (little endian):
+//   min              3 x f64  grid origin
+//   step             f64      grid step
+//   compressed size  u64      bytes of the deflated stream following
+//   deflated stream  one varint per point, difference to the previous Morton code
+// Sorting changes the point order: the attribute channels are stored in the new
+// order too, so each point keeps its values.
+// The reconstruction error of each point is at most half a step on each axis, that
+// is step * sqrt(3) / 2, plus the f32 rounding of the decoded positions.
+
+use crate::error::{Error, Result};
+use crate::Point3;
+
+use std::io::Read;
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+// Bits per axis in a 64 bits Morton code.
+const AXIS_BITS: u32 = 21;
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionOptions {
+    // Grid step, relative to the largest extent of the bounding box.
+    pub relative_step: f64,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        CompressionOptions { relative_step: 1e-5 }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionReport {
+    pub points: usize,
+    pub step: f64,
+    // Largest distance between an original position and its decoded one.
+    pub max_error: f64,
+}
+
+
+// Positions quantized on the grid, sorted by Morton code.
+pub(crate) struct QuantizedPoints {
+    min: [f64; 3],
+    step: f64,
+    codes: Vec<u64>,
+    // Original index of each sorted point.
+    pub order: Vec<usize>,
+}
+
+
+pub(crate) fn quantize(points: &[Point3<f32>], options: &CompressionOptions) -> Result<QuantizedPoints> {
+    if points.iter().any(|point| !(point.x.is_finite() && point.y.is_finite() && point.z.is_finite())) {
+        return Err(Error::InvalidInput("cannot quantize non-finite positions".to_string()));
+    }
+    if options.relative_step.is_nan() || options.relative_step <= 0. {
+        return Err(Error::InvalidInput(format!("invalid quantization step {}", options.relative_step)));
+    }
+
+    let mut min = [f64::INFINITY; 3];
+    let mut max = [f64::NEG_INFINITY; 3];
+    for point in points {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(point[axis] as f64);
+            max[axis] = max[axis].max(point[axis] as f64);
+        }
+    }
+
+    let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0., f64::max);
+    let step = match extent > 0. {
+        true => options.relative_step * extent,
+        false => 1.,
+    };
+    let cells = (extent / step).round();
+    if cells >= (1u64 << AXIS_BITS) as f64 {
+        return Err(Error::InvalidInput(format!(
+            "quantization step {} too small, the grid needs at most {} cells per axis", options.relative_step, 1u64 << AXIS_BITS)));
+    }
+
+    let mut coded: Vec<(u64, usize)> = points.iter()
+        .enumerate()
+        .map(|(index, point)| {
+            let cell = |axis: usize| ((point[axis] as f64 - min[axis]) / step).round() as u64;
+            (morton_encode([cell(0), cell(1), cell(2)]), index)
+        })
+        .collect();
+    coded.sort_unstable();
+
+    Ok(QuantizedPoints {
+        min,
+        step,
+        codes: coded.iter().map(|&(code, _)| code).collect(),
+        order: coded.iter().map(|&(_, index)| index).collect(),
+    })
+}
+
+
+impl QuantizedPoints {
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let mut previous = 0;
+        let mut buffer = Vec::with_capacity(10);
+        for &code in &self.codes {
+            buffer.clear();
+            write_varint(&mut buffer, code - previous);
+            encoder.write_all(&buffer)?;
+            previous = code;
+        }
+        let compressed = encoder.finish()?;
+
+        for value in self.min {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&self.step.to_le_bytes())?;
+        writer.write_all(&(compressed.len() as u64).to_le_bytes())?;
+        writer.write_all(&compressed)?;
+        Ok(())
+    }
+
+    pub(crate) fn decoded(&self) -> impl Iterator<Item = Point3<f32>> + '_ {
+        self.codes.iter().map(|&code| decode_point(code, &self.min, self.step))
+    }
+
+    pub(crate) fn report(&self, points: &[Point3<f32>]) -> CompressionReport {
+        let max_error = self.decoded()
+            .zip(&self.order)
+            .map(|(decoded, &index)| {
+                let original = &points[index];
+                let squared: f64 = (0..3).map(|axis| (decoded[axis] as f64 - original[axis] as f64).powi(2)).sum();
+                squared.sqrt()
+            })
+            .fold(0., f64::max);
+        CompressionReport { points: points.len(), step: self.step, max_error }
+    }
+}
+
+
+// Reads the positions block, leaving the reader at the attribute channels.
+pub(crate) fn read_quantized<R: Read>(reader: &mut R, point_count: u64, remaining_size: u64) -> Result<Vec<Point3<f32>>> {
+    let mut buffer = [0u8; 40];
+    reader.read_exact(&mut buffer)?;
+    let value = |index: usize| f64::from_le_bytes(buffer[index * 8..index * 8 + 8].try_into().unwrap());
+    let min = [value(0), value(1), value(2)];
+    let step = value(3);
+    let compressed_size = u64::from_le_bytes(buffer[32..40].try_into().unwrap());
+    if compressed_size > remaining_size.saturating_sub(40) {
+        return Err(Error::Truncated { expected: compressed_size + 40, found: remaining_size });
+    }
+
+    let mut decoder = ZlibDecoder::new(reader.by_ref().take(compressed_size));
+    let mut stream = Vec::new();
+    decoder.read_to_end(&mut stream)?;
+
+    let mut stream = &stream[..];
+    let mut points = Vec::with_capacity(point_count.min(stream.len() as u64) as usize);
+    let mut code = 0u64;
+    for _ in 0..point_count {
+        code = code.checked_add(read_varint(&mut stream)?)
+            .ok_or_else(|| Error::Malformed("quantized position out of range".to_string()))?;
+        points.push(decode_point(code, &min, step));
+    }
+    if !stream.is_empty() {
+        return Err(Error::Malformed("trailing bytes after the quantized positions".to_string()));
+    }
+    Ok(points)
+}
+
+
+fn decode_point(code: u64, min: &[f64; 3], step: f64) -> Point3<f32> {
+    let cells = morton_decode(code);
+    let value = |axis: usize| (min[axis] + cells[axis] as f64 * step) as f32;
+    Point3::new(value(0), value(1), value(2))
+}
+
+
+// Spreads the low 21 bits of the value to every third bit.
+fn spread_bits(value: u64) -> u64 {
+    let mut value = value & 0x1f_ffff;
+    value = (value | value << 32) & 0x001f_0000_0000_ffff;
+    value = (value | value << 16) & 0x001f_0000_ff00_00ff;
+    value = (value | value << 8) & 0x100f_00f0_0f00_f00f;
+    value = (value | value << 4) & 0x10c3_0c30_c30c_30c3;
+    value = (value | value << 2) & 0x1249_2492_4924_9249;
+    value
+}
+
+
+fn compact_bits(value: u64) -> u64 {
+    let mut value = value & 0x1249_2492_4924_9249;
+    value = (value | value >> 2) & 0x10c3_0c30_c30c_30c3;
+    value = (value | value >> 4) & 0x100f_00f0_0f00_f00f;
+    value = (value | value >> 8) & 0x001f_0000_ff00_00ff;
+    value = (value | value >> 16) & 0x001f_0000_0000_ffff;
+    value = (value | value >> 32) & 0x1f_ffff;
+    value
+}
+
+
+fn morton_encode(cells: [u64; 3]) -> u64 {
+    spread_bits(cells[0]) | spread_bits(cells[1]) << 1 | spread_bits(cells[2]) << 2
+}
+
+
+fn morton_decode(code: u64) -> [u64; 3] {
+    [compact_bits(code), compact_bits(code >> 1), compact_bits(code >> 2)]
+}
+
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()
+            .ok_or_else(|| Error::Malformed("quantized positions end early".to_string()))?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::Malformed("varint too long".to_string()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AttributeData, PointCloud};
+
+    #[test]
+    fn morton_and_varint_test() {
+        for cells in [[0, 0, 0], [1, 2, 3], [0x1f_ffff, 0, 0x1f_ffff], [12345, 678910, 1048576]] {
+            assert_eq!(cells, morton_decode(morton_encode(cells)));
+        }
+        assert_eq!(0b111, morton_encode([1, 1, 1]));
+
+        let mut buffer = Vec::new();
+        for value in [0, 127, 128, 300, u64::MAX] {
+            write_varint(&mut buffer, value);
+        }
+        let mut bytes = &buffer[..];
+        for value in [0, 127, 128, 300, u64::MAX] {
+            assert_eq!(value, read_varint(&mut bytes).unwrap());
+        }
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn compressed_file_test() {
+        let test_path = std::env::temp_dir().join("ps_data_layer_compressed.bin");
+
+        // A noisy sphere, with an index channel to follow the points through the reordering.
+        let points: Vec<Point3<f32>> = (0..20000)
+            .map(|index| {
+                let (theta, phi) = (index as f32 * 0.618, index as f32 * 0.001);
+                Point3::new(10. * theta.cos() * phi.sin(), 10. * theta.sin() * phi.sin(), 10. * phi.cos())
+            })
+            .collect();
+        let mut cloud = PointCloud::new(points.clone());
+        cloud.set_attribute("index", AttributeData::Label((0..20000).collect())).unwrap();
+
+        let options = CompressionOptions { relative_step: 1e-4 };
+        let report = cloud.write_compressed(&test_path, &options).expect("Writing failed");
+        assert!(report.max_error <= report.step * 3f64.sqrt() / 2. + 1e-5, "Error {} above the bound", report.max_error);
+
+        let loaded = PointCloud::new_from_file(&test_path).expect("Reading failed");
+        assert_eq!(points.len(), loaded.points.len());
+        let Some(AttributeData::Label(indices)) = loaded.get_attribute("index") else { panic!("Missing index channel") };
+        for (point, &index) in loaded.points.iter().zip(indices) {
+            assert!(kiss3d::nalgebra::distance(point, &points[index as usize]) as f64 <= report.max_error + 1e-6);
+        }
+
+        // Positions alone take less than half of the raw size.
+        let positions = PointCloud::new(points.clone());
+        positions.write_compressed(&test_path, &options).unwrap();
+        assert!(std::fs::metadata(&test_path).unwrap().len() < 20000 * 12 / 2, "Poor compression");
+
+        // Grids too fine for the Morton codes are refused.
+        assert!(cloud.write_compressed(&test_path, &CompressionOptions { relative_step: 1e-9 }).is_err());
+    }
+}
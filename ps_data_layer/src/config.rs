@@ -0,0 +1,149 @@
+// Location of the working points file shared by ps_cli and ps_visualizer.
+// Resolved from, in order:
+//   1. a command line flag (--points-file)
+//   2. the PS_POINTS_FILE environment variable
+//   3. the points_file key of a ps_config.toml file, in the current folder or the
+//      closest parent folder having one; relative paths start from that folder
+//   4. maps/points.bin
+// The config file holds lines like:
+//   # Experiment A
+//   points_file = "maps/experiment_a.bin"
+// Other keys and tables may be there for other tools, they are not read.
+
+use crate::error::{Error, Result};
+
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_POINTS_FILE: &str = "maps/points.bin";
+pub const POINTS_FILE_VARIABLE: &str = "PS_POINTS_FILE";
+pub const CONFIG_FILE: &str = "ps_config.toml";
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSource {
+    Flag,
+    Environment,
+    ConfigFile(PathBuf),
+    Default,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkingFile {
+    pub path: PathBuf,
+    pub source: PathSource,
+}
+
+
+pub fn resolve_points_file(i_flag: Option<&Path>) -> Result<WorkingFile> {
+    let current_folder = std::env::current_dir()?;
+    resolve_from(i_flag, std::env::var_os(POINTS_FILE_VARIABLE).map(PathBuf::from), &current_folder)
+}
+
+
+fn resolve_from(flag: Option<&Path>, variable: Option<PathBuf>, current_folder: &Path) -> Result<WorkingFile> {
+    if let Some(path) = flag {
+        return Ok(WorkingFile { path: path.to_path_buf(), source: PathSource::Flag });
+    }
+
+    if let Some(path) = variable.filter(|path| !path.as_os_str().is_empty()) {
+        return Ok(WorkingFile { path, source: PathSource::Environment });
+    }
+
+    for folder in current_folder.ancestors() {
+        let config_path = folder.join(CONFIG_FILE);
+        if !config_path.is_file() {
+            continue;
+        }
+        if let Some(path) = read_points_file_key(&config_path)? {
+            return Ok(WorkingFile { path: folder.join(path), source: PathSource::ConfigFile(config_path) });
+        }
+    }
+
+    Ok(WorkingFile { path: PathBuf::from(DEFAULT_POINTS_FILE), source: PathSource::Default })
+}
+
+
+// Looks for the top level points_file key only, which must hold a string.
+fn read_points_file_key(config_path: &Path) -> Result<Option<PathBuf>> {
+    let text = std::fs::read_to_string(config_path)?;
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        // Keys after a table header belong to the table.
+        if line.starts_with('[') {
+            break;
+        }
+        let Some((key, value)) = line.split_once('=') else { continue };
+        if key.trim() != "points_file" {
+            continue;
+        }
+
+        return match parse_string(value.trim()) {
+            Some(value) => Ok(Some(PathBuf::from(value))),
+            None => Err(Error::Malformed(format!("{} line {}: expected points_file = \"path\"", config_path.display(), number + 1))),
+        };
+    }
+
+    Ok(None)
+}
+
+
+// A TOML basic ("...") or literal ('...') string, optionally followed by a comment.
+fn parse_string(value: &str) -> Option<String> {
+    let mut chars = value.chars();
+    let quote = chars.next().filter(|&first| first == '"' || first == '\'')?;
+    let mut parsed = String::new();
+    while let Some(character) = chars.next() {
+        match character {
+            character if character == quote => {
+                let rest = chars.as_str().trim_start();
+                return (rest.is_empty() || rest.starts_with('#')).then_some(parsed);
+            }
+            '\\' if quote == '"' => match chars.next()? {
+                '\\' => parsed.push('\\'),
+                '"' => parsed.push('"'),
+                _ => return None,
+            },
+            character => parsed.push(character),
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_points_file_test() {
+        let project = std::env::temp_dir().join("ps_data_layer_config");
+        let nested = project.join("experiments").join("a");
+        std::fs::create_dir_all(&nested).unwrap();
+        let _ = std::fs::remove_file(project.join(CONFIG_FILE));
+
+        // Without anything set.
+        let resolved = resolve_from(None, None, &nested).unwrap();
+        assert_eq!(PathSource::Default, resolved.source);
+        assert_eq!(Path::new(DEFAULT_POINTS_FILE), resolved.path);
+
+        // A config file in a parent folder.
+        std::fs::write(project.join(CONFIG_FILE), "# Experiment\nother = \"x\"\nsteps = 10\nverbose = true\n\
+            points_file = \"maps/a.bin\" # shared\n[visualizer]\npoints_file = \"other.bin\"\n").unwrap();
+        let resolved = resolve_from(None, None, &nested).unwrap();
+        assert_eq!(PathSource::ConfigFile(project.join(CONFIG_FILE)), resolved.source);
+        assert_eq!(project.join("maps/a.bin"), resolved.path);
+
+        // Environment variable, then flag, take precedence.
+        let resolved = resolve_from(None, Some(PathBuf::from("env.bin")), &nested).unwrap();
+        assert_eq!((PathSource::Environment, PathBuf::from("env.bin")), (resolved.source, resolved.path));
+        let resolved = resolve_from(Some(Path::new("flag.bin")), Some(PathBuf::from("env.bin")), &nested).unwrap();
+        assert_eq!((PathSource::Flag, PathBuf::from("flag.bin")), (resolved.source, resolved.path));
+
+        // Malformed config files are reported.
+        std::fs::write(project.join(CONFIG_FILE), "points_file = maps/a.bin\n").unwrap();
+        assert!(matches!(resolve_from(None, None, &nested), Err(Error::Malformed(_))));
+        std::fs::remove_file(project.join(CONFIG_FILE)).unwrap();
+    }
+}
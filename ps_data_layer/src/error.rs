@@ -0,0 +1,63 @@
+// Errors of the data layer.
+
+use std::fmt;
+use std::path::PathBuf;
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+
+    // The file is shorter than its header says.
+    Truncated { expected: u64, found: u64 },
+
+    // Missing magic bytes or inconsistent header fields.
+    BadHeader(String),
+
+    UnsupportedVersion(u16),
+
+    // The path cannot be used, e.g. it has no parent folder or does not exist.
+    InvalidPath { path: PathBuf, reason: String },
+
+    // Content that does not follow its format.
+    Malformed(String),
+
+    // Valid content using a feature that is not implemented, e.g. compressed data.
+    Unsupported(String),
+
+    // Arguments that cannot be written, e.g. an attribute with the wrong length.
+    InvalidInput(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(formatter, "I/O error: {}", error),
+            Error::Truncated { expected, found } => write!(formatter, "truncated file: expected {} bytes, found {}", expected, found),
+            Error::BadHeader(message) => write!(formatter, "bad header: {}", message),
+            Error::UnsupportedVersion(version) => write!(formatter, "unsupported format version {}", version),
+            Error::InvalidPath { path, reason } => write!(formatter, "invalid path {}: {}", path.display(), reason),
+            Error::Malformed(message) => write!(formatter, "malformed data: {}", message),
+            Error::Unsupported(message) => write!(formatter, "unsupported: {}", message),
+            Error::InvalidInput(message) => write!(formatter, "invalid input: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
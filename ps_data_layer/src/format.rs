@@ -0,0 +1,41 @@
+// Supported file formats, chosen from the file extension.
+
+use std::path::Path;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    // The native points format, see header.rs
+    Points,
+    Ply,
+    // Whitespace separated text
+    Xyz,
+    // Comma separated text
+    Csv,
+    Las,
+    Pcd,
+    // NumPy positions array
+    Npy,
+    // NumPy archive, positions and attribute arrays
+    Npz,
+    // Wavefront OBJ vertices
+    Obj,
+}
+
+impl FileFormat {
+    pub fn from_path(path: &Path) -> Option<FileFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "bin" => Some(FileFormat::Points),
+            "ply" => Some(FileFormat::Ply),
+            "xyz" | "txt" | "asc" | "pts" => Some(FileFormat::Xyz),
+            "csv" => Some(FileFormat::Csv),
+            "las" => Some(FileFormat::Las),
+            "pcd" => Some(FileFormat::Pcd),
+            "npy" => Some(FileFormat::Npy),
+            "npz" => Some(FileFormat::Npz),
+            "obj" => Some(FileFormat::Obj),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,238 @@
+// Header of the points file format.
+// Layout (little endian, 24 bytes):
+//   magic           [u8; 4]  "PSPC"
+//   version         u16
+//   header size     u16      bytes from the start of the file to the first point
+//   scalar type     u8       see ScalarType
+//   encoding        u8       see Encoding, since version 2 (reserved before)
+//   reserved        [u8; 2]
+//   attributes      u32      bitmask of the attribute channels stored after the points
+//   point count     u64
+// Raw files are still written as version 1, so that older readers can open them.
+// Files not starting with the magic bytes are read as legacy headerless files
+// (raw f32 triples).
+
+use std::io::Read;
+use std::io::Write;
+use crate::error::{Error, Result};
+
+pub const MAGIC: [u8; 4] = *b"PSPC";
+pub const FORMAT_VERSION: u16 = 2;
+pub const HEADER_SIZE: u16 = 24;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarType {
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    pub fn from_code(code: u8) -> Option<ScalarType> {
+        match code {
+            1 => Some(ScalarType::F32),
+            2 => Some(ScalarType::F64),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            ScalarType::F32 => 1,
+            ScalarType::F64 => 2,
+        }
+    }
+
+    // Size in bytes of a single coordinate.
+    pub fn size(&self) -> usize {
+        match self {
+            ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+}
+
+
+// How the positions block is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    // Scalar triples.
+    Raw,
+    // Quantized, Morton ordered and compressed, see compression.rs.
+    Quantized,
+}
+
+impl Encoding {
+    pub fn from_code(code: u8) -> Option<Encoding> {
+        match code {
+            0 => Some(Encoding::Raw),
+            1 => Some(Encoding::Quantized),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Encoding::Raw => 0,
+            Encoding::Quantized => 1,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointsHeader {
+    pub version: u16,
+    pub header_size: u16,
+    pub scalar_type: ScalarType,
+    pub encoding: Encoding,
+    pub attributes: u32,
+    pub point_count: u64,
+}
+
+impl PointsHeader {
+    pub fn new(i_scalar_type: ScalarType, i_attributes: u32, i_point_count: u64) -> PointsHeader {
+        PointsHeader {
+            version: 1,
+            header_size: HEADER_SIZE,
+            scalar_type: i_scalar_type,
+            encoding: Encoding::Raw,
+            attributes: i_attributes,
+            point_count: i_point_count,
+        }
+    }
+
+    pub fn with_encoding(mut self, i_encoding: Encoding) -> PointsHeader {
+        self.encoding = i_encoding;
+        self.version = match i_encoding {
+            Encoding::Raw => 1,
+            Encoding::Quantized => 2,
+        };
+        self
+    }
+
+    // Size in bytes of the positions block following the header, for raw files.
+    // Fails for point counts too large to fit in a file.
+    pub fn points_size(&self) -> Result<u64> {
+        self.point_count.checked_mul(3 * self.scalar_type.size() as u64)
+            .ok_or_else(|| Error::BadHeader(format!("point count {} is too large", self.point_count)))
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        writer.write_all(&self.header_size.to_le_bytes())?;
+        writer.write_all(&[self.scalar_type.code(), self.encoding.code(), 0, 0])?;
+        writer.write_all(&self.attributes.to_le_bytes())?;
+        writer.write_all(&self.point_count.to_le_bytes())?;
+        Ok(())
+    }
+
+    // Reads the header, magic bytes included. Leaves the reader at the first point.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<PointsHeader> {
+        let mut buffer = [0u8; HEADER_SIZE as usize];
+        reader.read_exact(&mut buffer)?;
+
+        if buffer[0..4] != MAGIC {
+            return Err(Error::BadHeader("missing magic bytes".to_string()));
+        }
+
+        let version = u16::from_le_bytes([buffer[4], buffer[5]]);
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let header_size = u16::from_le_bytes([buffer[6], buffer[7]]);
+        if header_size < HEADER_SIZE {
+            return Err(Error::BadHeader(format!("header size {} is too small", header_size)));
+        }
+
+        let scalar_type = ScalarType::from_code(buffer[8])
+            .ok_or_else(|| Error::BadHeader(format!("unknown scalar type {}", buffer[8])))?;
+        let encoding = match version {
+            1 => Encoding::Raw,
+            _ => Encoding::from_code(buffer[9])
+                .ok_or_else(|| Error::BadHeader(format!("unknown encoding {}", buffer[9])))?,
+        };
+        let attributes = u32::from_le_bytes(buffer[12..16].try_into().unwrap());
+        let point_count = u64::from_le_bytes(buffer[16..24].try_into().unwrap());
+
+        // Skipping header fields added by newer minor revisions.
+        let extra = (header_size - HEADER_SIZE) as u64;
+        if extra > 0 {
+            std::io::copy(&mut reader.by_ref().take(extra), &mut std::io::sink())?;
+        }
+
+        Ok(PointsHeader { version, header_size, scalar_type, encoding, attributes, point_count })
+    }
+}
+
+
+// Checks whether the given bytes start a headered file.
+pub fn has_magic(bytes: &[u8]) -> bool {
+    bytes.len() >= MAGIC.len() && bytes[0..MAGIC.len()] == MAGIC
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_bytes(header: &PointsHeader) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        header.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn header_round_trip_test() {
+        for header in [
+            PointsHeader::new(ScalarType::F32, 0b101, 1000),
+            PointsHeader::new(ScalarType::F64, 0, u64::MAX).with_encoding(Encoding::Quantized),
+        ] {
+            let bytes = header_bytes(&header);
+            assert_eq!(HEADER_SIZE as usize, bytes.len());
+            assert!(has_magic(&bytes));
+            assert_eq!(header, PointsHeader::read_from(&mut &bytes[..]).expect("Reading failed"));
+        }
+        assert_eq!(2, PointsHeader::new(ScalarType::F64, 0, 0).with_encoding(Encoding::Quantized).version);
+
+        // Fields added by newer revisions are skipped.
+        let mut bytes = header_bytes(&PointsHeader::new(ScalarType::F32, 0, 2));
+        bytes[6..8].copy_from_slice(&(HEADER_SIZE + 4).to_le_bytes());
+        bytes.extend([0xAA; 4]);
+        bytes.push(7);
+        let mut reader = &bytes[..];
+        assert_eq!(HEADER_SIZE + 4, PointsHeader::read_from(&mut reader).unwrap().header_size);
+        assert_eq!([7], reader);
+
+        assert_eq!(24, PointsHeader::new(ScalarType::F64, 0, 1).points_size().unwrap());
+        assert!(matches!(PointsHeader::new(ScalarType::F32, 0, u64::MAX).points_size(), Err(Error::BadHeader(_))));
+    }
+
+    #[test]
+    fn header_rejection_test() {
+        let bytes = header_bytes(&PointsHeader::new(ScalarType::F32, 0, 2).with_encoding(Encoding::Quantized));
+        let read = |change: &dyn Fn(&mut Vec<u8>)| {
+            let mut changed = bytes.clone();
+            change(&mut changed);
+            PointsHeader::read_from(&mut &changed[..])
+        };
+
+        assert!(matches!(read(&|bytes| bytes[0..4].copy_from_slice(b"PSPX")), Err(Error::BadHeader(_))));
+        assert!(!has_magic(b"PSP"));
+        assert!(matches!(read(&|bytes| bytes[4..6].copy_from_slice(&0u16.to_le_bytes())), Err(Error::UnsupportedVersion(0))));
+        assert!(matches!(read(&|bytes| bytes[4..6].copy_from_slice(&3u16.to_le_bytes())), Err(Error::UnsupportedVersion(3))));
+        assert!(matches!(read(&|bytes| bytes[6..8].copy_from_slice(&20u16.to_le_bytes())), Err(Error::BadHeader(_))));
+        assert!(matches!(read(&|bytes| bytes[8] = 9), Err(Error::BadHeader(_))));
+        assert!(matches!(read(&|bytes| bytes[9] = 9), Err(Error::BadHeader(_))));
+        assert!(matches!(read(&|bytes| bytes.truncate(20)), Err(Error::Io(_))));
+
+        // Version 1 files have no encoding, the byte was reserved.
+        let header = read(&|bytes| {
+            bytes[4..6].copy_from_slice(&1u16.to_le_bytes());
+            bytes[9] = 9;
+        });
+        assert_eq!(Encoding::Raw, header.unwrap().encoding);
+    }
+}
@@ -0,0 +1,419 @@
+// LAS 1.2 - 1.4 import and export, point data record formats 0 to 3.
+// Coordinates are stored as scaled integers: position = record * scale + offset.
+// The record fields are mapped to attribute channels:
+//   intensity            -> ScalarF32 "intensity"
+//   return number        -> Label "return_number"
+//   number of returns    -> Label "number_of_returns"
+//   classification       -> Label "classification" (class only, flag bits dropped)
+//   point source id      -> Label "point_source_id"
+//   gps time (1, 3)      -> ScalarF64 "gps_time"
+//   red, green, blue     -> Color "color" (2, 3)
+// Scan angle and user data are not kept. Variable length records are skipped.
+// Positions end up in f32: large georeferenced coordinates lose precision.
+
+use crate::{AttributeData, PointCloud};
+use kiss3d::nalgebra::Point3;
+
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+use std::io::BufReader;
+use std::io::Write;
+use std::io::BufWriter;
+use crate::error::{Error, Result};
+
+const SIGNATURE: [u8; 4] = *b"LASF";
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LasHeader {
+    pub version_minor: u8,
+    pub header_size: u16,
+    pub point_data_offset: u32,
+    pub point_format: u8,
+    pub point_record_length: u16,
+    pub point_count: u64,
+    pub scale: [f64; 3],
+    pub offset: [f64; 3],
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl LasHeader {
+    fn header_size_for(version_minor: u8) -> u16 {
+        match version_minor {
+            2 => 227,
+            3 => 235,
+            _ => 375,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct LasWriteOptions {
+    // 2, 3 or 4 for LAS 1.2, 1.3, 1.4
+    pub version_minor: u8,
+
+    // Coordinates resolution, None picks the finest one fitting the cloud extent.
+    pub scale: Option<f64>,
+}
+
+impl Default for LasWriteOptions {
+    fn default() -> Self {
+        LasWriteOptions { version_minor: 2, scale: None }
+    }
+}
+
+
+// Reading
+
+pub fn read_las(file_path: &Path) -> Result<PointCloud> {
+    let file = File::open(file_path)?;
+    let file_size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let header = read_header(&mut reader)?;
+
+    // The point count comes from the file, it must fit in it before anything is allocated.
+    let expected_size = header.point_count.checked_mul(header.point_record_length as u64)
+        .and_then(|size| size.checked_add(header.point_data_offset as u64))
+        .ok_or_else(|| parse_error(format!("point count {} is too large", header.point_count)))?;
+    if file_size < expected_size {
+        return Err(Error::Truncated { expected: expected_size, found: file_size });
+    }
+
+    // Skipping the variable length records.
+    let skip = header.point_data_offset as u64 - header.header_size as u64;
+    std::io::copy(&mut reader.by_ref().take(skip), &mut std::io::sink())?;
+
+    let has_gps_time = header.point_format == 1 || header.point_format == 3;
+    let has_color = header.point_format == 2 || header.point_format == 3;
+    let count = header.point_count as usize;
+
+    let mut points = Vec::with_capacity(count);
+    let mut intensities = Vec::with_capacity(count);
+    let mut return_numbers = Vec::with_capacity(count);
+    let mut numbers_of_returns = Vec::with_capacity(count);
+    let mut classifications = Vec::with_capacity(count);
+    let mut point_source_ids = Vec::with_capacity(count);
+    let mut gps_times = Vec::new();
+    let mut colors: Vec<[u16; 3]> = Vec::new();
+
+    let mut record = vec![0u8; header.point_record_length as usize];
+    for _ in 0..count {
+        reader.read_exact(&mut record)?;
+
+        let x = i32::from_le_bytes(record[0..4].try_into().unwrap()) as f64 * header.scale[0] + header.offset[0];
+        let y = i32::from_le_bytes(record[4..8].try_into().unwrap()) as f64 * header.scale[1] + header.offset[1];
+        let z = i32::from_le_bytes(record[8..12].try_into().unwrap()) as f64 * header.scale[2] + header.offset[2];
+        points.push(Point3::new(x as f32, y as f32, z as f32));
+
+        intensities.push(u16::from_le_bytes([record[12], record[13]]) as f32);
+        return_numbers.push((record[14] & 0b111) as u32);
+        numbers_of_returns.push(((record[14] >> 3) & 0b111) as u32);
+        classifications.push((record[15] & 0b11111) as u32);
+        point_source_ids.push(u16::from_le_bytes([record[18], record[19]]) as u32);
+
+        let mut position = 20;
+        if has_gps_time {
+            gps_times.push(f64::from_le_bytes(record[20..28].try_into().unwrap()));
+            position = 28;
+        }
+        if has_color {
+            let channel = |index: usize| u16::from_le_bytes([record[position + 2 * index], record[position + 2 * index + 1]]);
+            colors.push([channel(0), channel(1), channel(2)]);
+        }
+    }
+
+    let mut cloud = PointCloud::new(points);
+    cloud.set_attribute("intensity", AttributeData::ScalarF32(intensities))?;
+    cloud.set_attribute("return_number", AttributeData::Label(return_numbers))?;
+    cloud.set_attribute("number_of_returns", AttributeData::Label(numbers_of_returns))?;
+    cloud.set_attribute("classification", AttributeData::Label(classifications))?;
+    cloud.set_attribute("point_source_id", AttributeData::Label(point_source_ids))?;
+    if has_gps_time {
+        cloud.set_attribute("gps_time", AttributeData::ScalarF64(gps_times))?;
+    }
+    if has_color {
+        // Plenty of writers store 8 bit colors in the 16 bit fields.
+        let eight_bits = colors.iter().all(|color| color.iter().all(|&channel| channel <= 255));
+        let to_byte = |channel: u16| if eight_bits { channel as u8 } else { (channel >> 8) as u8 };
+        let colors = colors.iter().map(|color| [to_byte(color[0]), to_byte(color[1]), to_byte(color[2])]).collect();
+        cloud.set_attribute("color", AttributeData::Color(colors))?;
+    }
+    Ok(cloud)
+}
+
+
+pub fn read_header<R: Read>(reader: &mut R) -> Result<LasHeader> {
+    let mut buffer = vec![0u8; 227];
+    reader.read_exact(&mut buffer)?;
+
+    if buffer[0..4] != SIGNATURE {
+        return Err(parse_error("missing LASF signature".to_string()));
+    }
+    let (version_major, version_minor) = (buffer[24], buffer[25]);
+    if version_major != 1 || !(2..=4).contains(&version_minor) {
+        return Err(Error::Unsupported(format!("unsupported LAS version {}.{}", version_major, version_minor)));
+    }
+
+    let u16_at = |offset: usize| u16::from_le_bytes([buffer[offset], buffer[offset + 1]]);
+    let u32_at = |offset: usize| u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap());
+    let f64_at = |offset: usize| f64::from_le_bytes(buffer[offset..offset + 8].try_into().unwrap());
+
+    let header_size = u16_at(94);
+    let point_data_offset = u32_at(96);
+    let point_format = buffer[104];
+    let point_record_length = u16_at(105);
+    let legacy_point_count = u32_at(107);
+
+    if point_format & 0x80 != 0 {
+        return Err(Error::Unsupported("compressed LAZ data is not supported".to_string()));
+    }
+    let point_format = point_format & 0x3f;
+    if point_format > 3 {
+        return Err(Error::Unsupported(format!("unsupported point data record format {}", point_format)));
+    }
+    let minimum_length = [20, 28, 26, 34][point_format as usize];
+    if point_record_length < minimum_length {
+        return Err(parse_error(format!("record length {} too short for format {}", point_record_length, point_format)));
+    }
+    if header_size < LasHeader::header_size_for(version_minor) || point_data_offset < header_size as u32 {
+        return Err(parse_error(format!("bad header size {} or point data offset {}", header_size, point_data_offset)));
+    }
+
+    let mut header = LasHeader {
+        version_minor,
+        header_size,
+        point_data_offset,
+        point_format,
+        point_record_length,
+        point_count: legacy_point_count as u64,
+        scale: [f64_at(131), f64_at(139), f64_at(147)],
+        offset: [f64_at(155), f64_at(163), f64_at(171)],
+        min: [f64_at(187), f64_at(203), f64_at(219)],
+        max: [f64_at(179), f64_at(195), f64_at(211)],
+    };
+
+    // Rest of the header, holding the 64 bit point count in LAS 1.4.
+    let mut extension = vec![0u8; header_size as usize - 227];
+    reader.read_exact(&mut extension)?;
+    if version_minor == 4 && legacy_point_count == 0 {
+        header.point_count = u64::from_le_bytes(extension[247 - 227..255 - 227].try_into().unwrap());
+    }
+
+    Ok(header)
+}
+
+
+// Writing
+
+pub fn write_las(file_path: &Path, cloud: &PointCloud, options: &LasWriteOptions) -> Result<usize> {
+    if !(2..=4).contains(&options.version_minor) {
+        return Err(Error::InvalidInput(format!("cannot write LAS 1.{}", options.version_minor)));
+    }
+
+    let intensities = scalar_channel(cloud, "intensity");
+    let gps_times = scalar_channel(cloud, "gps_time");
+    let return_numbers = label_channel(cloud, "return_number");
+    let numbers_of_returns = label_channel(cloud, "number_of_returns");
+    let classifications = label_channel(cloud, "classification");
+    let point_source_ids = label_channel(cloud, "point_source_id");
+    let colors = match cloud.get_attribute("color") {
+        Some(AttributeData::Color(colors)) => Some(colors),
+        _ => None,
+    };
+
+    let point_format: u8 = match (gps_times.is_some(), colors.is_some()) {
+        (false, false) => 0,
+        (true, false) => 1,
+        (false, true) => 2,
+        (true, true) => 3,
+    };
+    let record_length: u16 = [20, 28, 26, 34][point_format as usize];
+
+    // Bounds, offset and scale
+    let mut min = [f64::MAX; 3];
+    let mut max = [f64::MIN; 3];
+    for point in &cloud.points {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(point[axis] as f64);
+            max[axis] = max[axis].max(point[axis] as f64);
+        }
+    }
+    if cloud.points.is_empty() {
+        min = [0.; 3];
+        max = [0.; 3];
+    }
+    let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0., f64::max);
+    let scale = options.scale.unwrap_or_else(|| automatic_scale(extent));
+    if extent / scale > i32::MAX as f64 {
+        return Err(Error::InvalidInput(format!("scale {} too fine for an extent of {}", scale, extent)));
+    }
+    let offset = min;
+
+    let header_size = LasHeader::header_size_for(options.version_minor);
+    let point_count = cloud.points.len() as u64;
+    let legacy_point_count = if point_count <= u32::MAX as u64 { point_count as u32 } else { 0 };
+    if legacy_point_count == 0 && point_count > 0 && options.version_minor < 4 {
+        return Err(Error::InvalidInput("too many points for LAS 1.2 and 1.3".to_string()));
+    }
+
+    let mut points_by_return = [0u64; 5];
+    for index in 0..cloud.points.len() {
+        let return_number = return_numbers.map_or(1, |values| values[index]) as usize;
+        if (1..=5).contains(&return_number) {
+            points_by_return[return_number - 1] += 1;
+        }
+    }
+
+    let file = File::create(file_path)?;
+    let mut writer = BufWriter::new(file);
+
+    // Public header block
+    let mut header = Vec::with_capacity(header_size as usize);
+    header.extend_from_slice(&SIGNATURE);
+    header.extend_from_slice(&[0u8; 20]); // file source id, global encoding, GUID
+    header.extend_from_slice(&[1, options.version_minor]);
+    header.extend_from_slice(&padded::<32>(b"ps_data_layer"));
+    header.extend_from_slice(&padded::<32>(b"ps_data_layer"));
+    header.extend_from_slice(&[0u8; 4]); // creation day and year
+    header.extend_from_slice(&header_size.to_le_bytes());
+    header.extend_from_slice(&(header_size as u32).to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes()); // variable length records
+    header.push(point_format);
+    header.extend_from_slice(&record_length.to_le_bytes());
+    header.extend_from_slice(&legacy_point_count.to_le_bytes());
+    for count in points_by_return {
+        header.extend_from_slice(&(count.min(u32::MAX as u64) as u32).to_le_bytes());
+    }
+    for _ in 0..3 {
+        header.extend_from_slice(&scale.to_le_bytes());
+    }
+    for value in offset {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    for axis in 0..3 {
+        header.extend_from_slice(&max[axis].to_le_bytes());
+        header.extend_from_slice(&min[axis].to_le_bytes());
+    }
+    if options.version_minor >= 3 {
+        header.extend_from_slice(&0u64.to_le_bytes()); // waveform data packet record
+    }
+    if options.version_minor >= 4 {
+        header.extend_from_slice(&0u64.to_le_bytes()); // first extended variable length record
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&point_count.to_le_bytes());
+        for count in points_by_return {
+            header.extend_from_slice(&count.to_le_bytes());
+        }
+        header.extend_from_slice(&[0u8; 80]); // points by return 6 to 15
+    }
+    writer.write_all(&header)?;
+
+    // Point records
+    for (index, point) in cloud.points.iter().enumerate() {
+        for axis in 0..3 {
+            let value = ((point[axis] as f64 - offset[axis]) / scale).round() as i32;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        let intensity = intensities.as_ref().map_or(0., |values| values[index]);
+        writer.write_all(&(intensity.round().clamp(0., u16::MAX as f64) as u16).to_le_bytes())?;
+
+        let return_number = return_numbers.map_or(1, |values| values[index]).min(7) as u8;
+        let number_of_returns = numbers_of_returns.map_or(1, |values| values[index]).min(7) as u8;
+        writer.write_all(&[return_number | (number_of_returns << 3)])?;
+        writer.write_all(&[classifications.map_or(0, |values| values[index]).min(31) as u8])?;
+        writer.write_all(&[0, 0])?; // scan angle rank, user data
+        writer.write_all(&(point_source_ids.map_or(0, |values| values[index]).min(u16::MAX as u32) as u16).to_le_bytes())?;
+
+        if let Some(gps_times) = &gps_times {
+            writer.write_all(&gps_times[index].to_le_bytes())?;
+        }
+        if let Some(colors) = colors {
+            for channel in colors[index] {
+                writer.write_all(&(channel as u16 * 257).to_le_bytes())?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(cloud.points.len())
+}
+
+
+// Finest power of ten scale keeping the extent within the i32 range.
+fn automatic_scale(extent: f64) -> f64 {
+    if extent <= 0. {
+        return 0.001;
+    }
+    10f64.powi((extent / 2e9).log10().ceil() as i32)
+}
+
+fn scalar_channel(cloud: &PointCloud, name: &str) -> Option<Vec<f64>> {
+    match cloud.get_attribute(name) {
+        Some(AttributeData::ScalarF32(values)) => Some(values.iter().map(|&value| value as f64).collect()),
+        Some(AttributeData::ScalarF64(values)) => Some(values.clone()),
+        _ => None,
+    }
+}
+
+fn label_channel<'a>(cloud: &'a PointCloud, name: &str) -> Option<&'a Vec<u32>> {
+    match cloud.get_attribute(name) {
+        Some(AttributeData::Label(values)) => Some(values),
+        _ => None,
+    }
+}
+
+fn padded<const N: usize>(text: &[u8]) -> [u8; N] {
+    let mut buffer = [0u8; N];
+    buffer[..text.len()].copy_from_slice(text);
+    buffer
+}
+
+fn parse_error(message: String) -> Error {
+    Error::Malformed(message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn las_round_trip_test() {
+        let mut cloud = PointCloud::new(vec![Point3::new(10.5, 20.25, -3.), Point3::new(11., 21., 4.125), Point3::new(10., 20., 0.)]);
+        cloud.set_attribute("intensity", AttributeData::ScalarF32(vec![100., 65535., 0.])).unwrap();
+        cloud.set_attribute("classification", AttributeData::Label(vec![2, 5, 6])).unwrap();
+        cloud.set_attribute("gps_time", AttributeData::ScalarF64(vec![1.5, 2.5, 3.5])).unwrap();
+        cloud.set_attribute("color", AttributeData::Color(vec![[255, 0, 0], [0, 255, 0], [1, 2, 3]])).unwrap();
+
+        for version_minor in 2..=4 {
+            let test_path = std::env::temp_dir().join(format!("ps_data_layer_1{}.las", version_minor));
+            let options = LasWriteOptions { version_minor, ..LasWriteOptions::default() };
+            assert_eq!(3, write_las(&test_path, &cloud, &options).expect("Writing failed"));
+
+            let header = read_header(&mut File::open(&test_path).unwrap()).unwrap();
+            assert_eq!((version_minor, 3, 3), (header.version_minor, header.point_format, header.point_count));
+
+            let read_cloud = read_las(&test_path).expect("Reading failed");
+            for (written, read) in cloud.points.iter().zip(read_cloud.points.iter()) {
+                assert!((written - read).norm() < 1e-5, "Position {} read back as {}", written, read);
+            }
+            for name in ["intensity", "classification", "gps_time", "color"] {
+                assert_eq!(cloud.get_attribute(name), read_cloud.get_attribute(name), "Channel {} mismatch", name);
+            }
+            assert_eq!(Some(&AttributeData::Label(vec![1, 1, 1])), read_cloud.get_attribute("return_number"));
+        }
+
+        // A point count larger than the file is rejected before allocating.
+        let test_path = std::env::temp_dir().join("ps_data_layer_count.las");
+        write_las(&test_path, &cloud, &LasWriteOptions::default()).unwrap();
+        let mut bytes = std::fs::read(&test_path).unwrap();
+        bytes[107..111].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&test_path, &bytes).unwrap();
+        assert!(matches!(read_las(&test_path), Err(Error::Truncated { .. })));
+    }
+}
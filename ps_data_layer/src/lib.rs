@@ -1,105 +1,301 @@
 // Geometry
-use kiss3d::nalgebra::Point3;
+pub use kiss3d::nalgebra::Point3;
 
 // Filesystem and I/O
 use std::fs::File;
 use std::path::Path;
-use std::io::Read;
+use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Write;
-use std::io::BufWriter;
 
-
-// Local paths:
-const POINTS_FILE: &str = "maps/points.bin";
+// Errors
+pub mod error;
+pub use error::{Error, Result};
+
+// File format
+pub mod atomic;
+pub use atomic::WriteOptions;
+pub mod header;
+use header::{Encoding, PointsHeader, ScalarType};
+pub mod attributes;
+pub use attributes::{Attribute, AttributeData, AttributeKind};
+pub mod format;
+pub use format::FileFormat;
+pub mod ply;
+use ply::PlyFormat;
+pub mod xyz;
+use xyz::XyzOptions;
+pub mod las;
+use las::LasWriteOptions;
+pub mod pcd;
+use pcd::PcdFormat;
+pub mod npy;
+pub mod obj;
+pub mod stream;
+pub use stream::{MappedPoints, PointChunks};
+pub mod compression;
+pub use compression::{CompressionOptions, CompressionReport};
+pub mod timeline;
+pub use timeline::{TimelineOptions, TimelineReader, TimelineWriter};
+
+// Working file location
+pub mod config;
+
+// Provenance sidecars, behind the serde feature
+#[cfg(feature = "serde")]
+pub mod metadata;
+#[cfg(feature = "serde")]
+pub use metadata::Metadata;
+
+// Serde support, behind the serde feature
+#[cfg(feature = "serde")]
+mod serde_points;
 
 // Points Cloud object
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PointCloud {
+    #[cfg_attr(feature = "serde", serde(with = "serde_points"))]
     pub points : Vec<Point3<f32>>,
+
+    // Named per-point channels, each holding exactly one value per point.
+    pub attributes : Vec<Attribute>,
+
+    // Provenance, saved in a sidecar next to the file (see metadata.rs).
+    #[cfg(feature = "serde")]
+    #[serde(default)]
+    pub metadata : Option<Metadata>,
 }
 
 
 impl PointCloud {
     pub fn new (i_points : Vec<Point3<f32>>) -> PointCloud {
-        PointCloud {points: i_points,}
+        PointCloud {
+            points: i_points,
+            attributes: Vec::new(),
+            #[cfg(feature = "serde")]
+            metadata: None,
+        }
     }
 
-    pub fn new_from_file(i_path : &Path) -> PointCloud{
-        PointCloud::new(read_points_from_binary(i_path).unwrap())
+    pub fn new_from_file(i_path : &Path) -> Result<PointCloud> {
+        let mut cloud = PointCloud::new(Vec::new());
+        cloud.read_from_file(i_path)?;
+        Ok(cloud)
     }
 
-    pub fn get_standard_file(&self) -> & 'static str {
-        POINTS_FILE
+    pub fn read_from_file(&mut self, i_path : &Path) -> Result<usize> {
+        let (points, attributes) = read_points_from_binary(i_path)?;
+        self.points = points;
+        self.attributes = attributes;
+        #[cfg(feature = "serde")]
+        {
+            self.metadata = read_metadata(i_path);
+        }
+        Ok(self.points.len())
+    }
+    
+    pub fn write_to_file(&self, i_path: &Path) -> Result<usize> {
+        self.write_to_file_with(i_path, &WriteOptions::default())
     }
 
-    pub fn read_from_file(&mut self, i_path : &Path) -> Option<usize> {
+    pub fn write_to_file_with(&self, i_path: &Path, i_options: &WriteOptions) -> Result<usize> {
+        let count = write_points_to_binary(i_path, &self.points, &self.attributes, i_options)?;
+        #[cfg(feature = "serde")]
+        self.write_metadata(i_path)?;
+        Ok(count)
+    }
 
-        if let Ok(points) = read_points_from_binary(i_path) {
-            self.points = points;
-            return Some(self.points.len());
-        }
-        None
+    // Quantized and compressed positions, see compression.rs. The points are stored in
+    // a different order, reading the file back gives them sorted along a Morton curve.
+    pub fn write_compressed(&self, i_path: &Path, i_options: &CompressionOptions) -> Result<CompressionReport> {
+        let report = write_compressed_points(i_path, &self.points, &self.attributes, i_options)?;
+        #[cfg(feature = "serde")]
+        self.write_metadata(i_path)?;
+        Ok(report)
     }
-    
-    pub fn write_to_file(&self, i_path: &Path) -> Option<usize> {
-        if let Ok(points) = write_points_to_binary(i_path, &self.points) {
-            return Some(points)
+
+    // Loading and saving in the format given by the file extension.
+    pub fn load(i_path: &Path) -> Result<PointCloud> {
+        let cloud = match FileFormat::from_path(i_path) {
+            Some(FileFormat::Points) => return PointCloud::new_from_file(i_path),
+            Some(FileFormat::Ply) => ply::read_ply(i_path)?.cloud,
+            Some(FileFormat::Xyz) => xyz::read_xyz(i_path, &XyzOptions::default())?,
+            Some(FileFormat::Csv) => xyz::read_xyz(i_path, &XyzOptions::csv().with_header_columns(i_path)?)?,
+            Some(FileFormat::Las) => las::read_las(i_path)?,
+            Some(FileFormat::Pcd) => pcd::read_pcd(i_path)?,
+            Some(FileFormat::Npy) => npy::read_npy(i_path)?,
+            Some(FileFormat::Npz) => npy::read_npz(i_path)?,
+            Some(FileFormat::Obj) => obj::read_obj(i_path)?,
+            None => return Err(unknown_format(i_path)),
+        };
+        #[cfg(feature = "serde")]
+        let cloud = PointCloud { metadata: read_metadata(i_path), ..cloud };
+        Ok(cloud)
+    }
+
+    pub fn save(&self, i_path: &Path) -> Result<usize> {
+        let count = match FileFormat::from_path(i_path) {
+            Some(FileFormat::Points) => return self.write_to_file(i_path),
+            Some(FileFormat::Ply) => self.save_ply(i_path, PlyFormat::BinaryLittleEndian)?,
+            Some(FileFormat::Xyz) => xyz::write_xyz(i_path, self, &XyzOptions::default().with_columns_for(self))?,
+            Some(FileFormat::Csv) => xyz::write_xyz(i_path, self, &XyzOptions { skip_lines: 1, ..XyzOptions::csv() }.with_columns_for(self))?,
+            Some(FileFormat::Las) => las::write_las(i_path, self, &LasWriteOptions::default())?,
+            Some(FileFormat::Pcd) => pcd::write_pcd(i_path, self, PcdFormat::Binary)?,
+            Some(FileFormat::Npy) => npy::write_npy(i_path, self)?,
+            Some(FileFormat::Npz) => npy::write_npz(i_path, self)?,
+            Some(FileFormat::Obj) => obj::write_cloud_obj(i_path, self)?,
+            None => return Err(unknown_format(i_path)),
+        };
+        #[cfg(feature = "serde")]
+        self.write_metadata(i_path)?;
+        Ok(count)
+    }
+
+    // Writes the sidecar of a file just saved, stamped with the save time and the file hash.
+    #[cfg(feature = "serde")]
+    pub fn write_metadata(&self, i_path: &Path) -> Result<()> {
+        let Some(metadata) = &self.metadata else {
+            return Ok(());
+        };
+        let mut metadata = metadata.clone();
+        metadata.saved = Some(metadata::utc_now());
+        metadata.content_hash = Some(metadata::file_hash(i_path)?);
+        metadata::write_sidecar(i_path, &metadata)
+    }
+
+    pub fn save_ply(&self, i_path: &Path, i_format: PlyFormat) -> Result<usize> {
+        ply::write_ply(i_path, self, &[], i_format)
+    }
+
+    // Attribute channels
+    pub fn get_attribute(&self, i_name: &str) -> Option<&AttributeData> {
+        self.attributes.iter()
+            .find(|attribute| attribute.name == i_name)
+            .map(|attribute| &attribute.data)
+    }
+
+    pub fn get_attribute_mut(&mut self, i_name: &str) -> Option<&mut AttributeData> {
+        self.attributes.iter_mut()
+            .find(|attribute| attribute.name == i_name)
+            .map(|attribute| &mut attribute.data)
+    }
+
+    // Adds a channel, replacing any channel with the same name.
+    // The channel must hold one value per point.
+    pub fn set_attribute(&mut self, i_name: &str, i_data: AttributeData) -> Result<()> {
+        if i_data.len() != self.points.len() {
+            return Err(Error::InvalidInput(format!(
+                "attribute {} has {} values for {} points", i_name, i_data.len(), self.points.len())));
         }
-        None
+
+        match self.get_attribute_mut(i_name) {
+            Some(data) => *data = i_data,
+            None => self.attributes.push(Attribute::new(i_name, i_data)),
+        }
+        Ok(())
+    }
+
+    pub fn remove_attribute(&mut self, i_name: &str) -> Option<AttributeData> {
+        let index = self.attributes.iter().position(|attribute| attribute.name == i_name)?;
+        Some(self.attributes.remove(index).data)
+    }
+
+    pub fn attribute_names(&self) -> Vec<&str> {
+        self.attributes.iter().map(|attribute| attribute.name.as_str()).collect()
+    }
+
+    // First channel of the given kind, useful for consumers looking for "a color" or "a normal".
+    pub fn find_attribute(&self, i_kind: AttributeKind) -> Option<&Attribute> {
+        self.attributes.iter().find(|attribute| attribute.data.kind() == i_kind)
     }
 
 }
 
 
 // Private functions 
-fn read_points_from_binary(file_path: &Path) -> std::io::Result<Vec<Point3<f32>>> {
+type PointsAndAttributes = (Vec<Point3<f32>>, Vec<Attribute>);
+
+fn read_points_from_binary(file_path: &Path) -> Result<PointsAndAttributes> {
+    if let Some(points) = read_compressed_points(file_path)? {
+        return Ok(points);
+    }
+
+    let mapped = MappedPoints::open(file_path)?;
+    let points: Vec<Point3<f32>> = match mapped.as_slice() {
+        Some(slice) => slice.iter().map(|&[x, y, z]| Point3::new(x, y, z)).collect(),
+        None => mapped.iter().collect(),
+    };
+
+    let attributes = match mapped.header() {
+        Some(header) => read_attribute_section(&mut mapped.trailing_bytes(), header, points.len())?,
+        None => Vec::new(),
+    };
+
+    Ok((points, attributes))
+}
+
+
+// None for files without the compressed encoding.
+fn read_compressed_points(file_path: &Path) -> Result<Option<PointsAndAttributes>> {
     let file = File::open(file_path)?;
+    let file_size = file.metadata()?.len();
     let mut reader = BufReader::new(file);
-    let mut points = Vec::new();
-    let mut buffer = [0u8; 4 * 3]; // 3 f32 values, 4 bytes each
-
-    // TODO rewrite this, it's ugly!
-    while reader.read_exact(&mut buffer).is_ok() {
-        let x = f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
-        let y = f32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
-        let z = f32::from_le_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]);
+    if !header::has_magic(reader.fill_buf()?) {
+        return Ok(None);
+    }
 
-        points.push(Point3::new(x, y, z));
+    let header = PointsHeader::read_from(&mut reader)?;
+    if header.encoding != Encoding::Quantized {
+        return Ok(None);
     }
 
-    Ok(points)
+    let remaining_size = file_size.saturating_sub(header.header_size as u64);
+    let points = compression::read_quantized(&mut reader, header.point_count, remaining_size)?;
+    let attributes = read_attribute_section(&mut reader, &header, points.len())?;
+    Ok(Some((points, attributes)))
 }
 
 
-fn write_points_to_binary(file_path: &Path, points: &Vec<Point3<f32>>) -> std::io::Result<usize> {
-    println!("writing file {}", file_path.to_str().unwrap());
+fn read_attribute_section<R: std::io::Read>(reader: &mut R, header: &PointsHeader, point_count: usize) -> Result<Vec<Attribute>> {
+    if header.attributes == 0 {
+        return Ok(Vec::new());
+    }
 
-    // Creating the path if necessary:
-    let prefix = file_path.parent().unwrap();
-    println!("Creating folder {}", prefix.to_str().unwrap());
-    std::fs::create_dir_all(prefix).unwrap();
+    let attributes = attributes::read_attributes(reader, point_count)?;
+    if attributes::attributes_bitmask(&attributes) != header.attributes {
+        return Err(Error::BadHeader("attribute channels do not match the header bitmask".to_string()));
+    }
+    Ok(attributes)
+}
 
-    // Creating the file.
-    let file = File::create(file_path);
-    if file.is_err() {
-        panic!("Cannot write file {}", file_path.as_os_str().to_str().unwrap())
-    };
-    
-    let file = file?;
-    
-    println!("file created.");
-    let mut writer = BufWriter::new(file);
 
-    let mut points_counter = 0;
-    for &point in points {
-        writer.write_all(&point.x.to_le_bytes())?;
-        writer.write_all(&point.y.to_le_bytes())?;
-        writer.write_all(&point.z.to_le_bytes())?;
-        points_counter += 1;
+fn write_points_to_binary(file_path: &Path, points: &[Point3<f32>], attributes: &[Attribute], options: &WriteOptions) -> Result<usize> {
+    if let Some(attribute) = attributes.iter().find(|attribute| attribute.data.len() != points.len()) {
+        return Err(Error::InvalidInput(format!(
+            "attribute {} has {} values for {} points", attribute.name, attribute.data.len(), points.len())));
     }
 
-    writer.flush()?;
+    println!("writing file {}", file_path.display());
+
+    // Writing to a temporary file renamed into place, as the visualizer may be reading the target.
+    let points_counter = atomic::write_atomically(file_path, options, |writer| {
+        let bitmask = attributes::attributes_bitmask(attributes);
+        PointsHeader::new(ScalarType::F32, bitmask, points.len() as u64).write_to(writer)?;
+
+        let mut points_counter = 0;
+        for &point in points {
+            writer.write_all(&point.x.to_le_bytes())?;
+            writer.write_all(&point.y.to_le_bytes())?;
+            writer.write_all(&point.z.to_le_bytes())?;
+            points_counter += 1;
+        }
+
+        if bitmask != 0 {
+            attributes::write_attributes(writer, attributes)?;
+        }
+        Ok(points_counter)
+    })?;
 
     println!("Written {} points.", points_counter);
 
@@ -109,9 +305,58 @@ fn write_points_to_binary(file_path: &Path, points: &Vec<Point3<f32>>) -> std::i
 
 
 
+fn write_compressed_points(file_path: &Path, points: &[Point3<f32>], attributes: &[Attribute], options: &CompressionOptions) -> Result<CompressionReport> {
+    if let Some(attribute) = attributes.iter().find(|attribute| attribute.data.len() != points.len()) {
+        return Err(Error::InvalidInput(format!(
+            "attribute {} has {} values for {} points", attribute.name, attribute.data.len(), points.len())));
+    }
+
+    println!("writing compressed file {}", file_path.display());
+
+    let quantized = compression::quantize(points, options)?;
+    atomic::write_atomically(file_path, &WriteOptions::default(), |writer| {
+        let bitmask = attributes::attributes_bitmask(attributes);
+        PointsHeader::new(ScalarType::F32, bitmask, points.len() as u64)
+            .with_encoding(Encoding::Quantized)
+            .write_to(writer)?;
+        quantized.write_to(writer)?;
+
+        // Channels follow the points in their new order.
+        if bitmask != 0 {
+            let sorted: Vec<Attribute> = attributes.iter()
+                .map(|attribute| Attribute::new(&attribute.name, attribute.data.select(&quantized.order)))
+                .collect();
+            attributes::write_attributes(writer, &sorted)?;
+        }
+        Ok(())
+    })?;
+
+    Ok(quantized.report(points))
+}
+
+
+// A broken sidecar should not prevent reading the points.
+#[cfg(feature = "serde")]
+fn read_metadata(path: &Path) -> Option<Metadata> {
+    metadata::read_sidecar(path).unwrap_or_else(|e| {
+        eprintln!("Warning: ignoring the metadata of {}: {}", path.display(), e);
+        None
+    })
+}
+
+
+fn unknown_format(file_path: &Path) -> Error {
+    Error::Unsupported(format!("unknown file format for {}", file_path.display()))
+}
+
+
 // Utilities Functions:
-pub fn are_paths_same (path_a: &Path, path_b: &Path) -> bool {
-    std::fs::canonicalize(path_a).unwrap() == std::fs::canonicalize(path_b).unwrap()
+pub fn are_paths_same (path_a: &Path, path_b: &Path) -> Result<bool> {
+    let canonicalize = |path: &Path| std::fs::canonicalize(path).map_err(|error| Error::InvalidPath {
+        path: path.to_path_buf(),
+        reason: error.to_string(),
+    });
+    Ok(canonicalize(path_a)? == canonicalize(path_b)?)
 }
 
 
@@ -122,19 +367,82 @@ mod tests {
 
     #[test]
     fn read_write_test() {
-        let mut test_vec = Vec::<Point3<f32>>::new();
-        test_vec.push(Point3::new(42.,0.,1.));
-        test_vec.push(Point3::new(43.,1.,2.));
-        test_vec.push(Point3::new(44.,2.,3.));
+        let test_vec = vec![
+            Point3::new(42.,0.,1.),
+            Point3::new(43.,1.,2.),
+            Point3::new(44.,2.,3.)];
 
-        let test_path = Path::new(".\\test.bin");
+        let test_path = &std::env::temp_dir().join("ps_data_layer_read_write.bin");
         
         // Writing
-        assert_eq!(3, write_points_to_binary(&test_path, &test_vec).expect("Writing failed"), "Wrote the wrong amount of points");
+        assert_eq!(3, write_points_to_binary(test_path, &test_vec, &[], &WriteOptions::default()).expect("Writing failed"), "Wrote the wrong amount of points");
         
         // Reading
-        let read_vec = read_points_from_binary(&test_path).expect("Reading failed");
+        let (read_vec, _) = read_points_from_binary(test_path).expect("Reading failed");
         assert_eq!(3, read_vec.len(), "Read the wrong amount of points");
         assert_eq!(test_vec, read_vec, "input output vector mismatch");
     }
+
+    #[test]
+    fn legacy_and_truncated_test() {
+        let legacy_path = std::env::temp_dir().join("ps_data_layer_legacy.bin");
+        let mut bytes = Vec::new();
+        for value in [1f32, 2., 3., 4., 5., 6.] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        std::fs::write(&legacy_path, &bytes).unwrap();
+
+        let mut cloud = PointCloud::new(Vec::new());
+        assert_eq!(2, cloud.read_from_file(&legacy_path).expect("Legacy file not read"));
+        assert_eq!(Point3::new(4., 5., 6.), cloud.points[1]);
+
+        // A partial point at the end of a legacy file is an error, not a silent truncation.
+        std::fs::write(&legacy_path, &bytes[..bytes.len() - 2]).unwrap();
+        assert!(matches!(read_points_from_binary(&legacy_path), Err(Error::Truncated { .. })), "Truncated legacy file accepted");
+
+        // Same for a headered file shorter than its point count.
+        let headered_path = std::env::temp_dir().join("ps_data_layer_truncated.bin");
+        write_points_to_binary(&headered_path, &cloud.points, &[], &WriteOptions::default()).unwrap();
+        let full = std::fs::read(&headered_path).unwrap();
+        std::fs::write(&headered_path, &full[..full.len() - 12]).unwrap();
+        assert!(matches!(read_points_from_binary(&headered_path), Err(Error::Truncated { .. })), "Truncated headered file accepted");
+
+        // Newer versions are reported as such.
+        let mut future = full.clone();
+        future[4] = 99;
+        std::fs::write(&headered_path, &future).unwrap();
+        assert!(matches!(read_points_from_binary(&headered_path), Err(Error::UnsupportedVersion(99))));
+    }
+
+    #[test]
+    fn attributes_round_trip_test() {
+        let mut cloud = PointCloud::new(vec![Point3::new(0., 0., 0.), Point3::new(1., 2., 3.)]);
+        cloud.set_attribute("color", AttributeData::Color(vec![[255, 0, 0], [0, 128, 255]])).unwrap();
+        cloud.set_attribute("normal", AttributeData::Normal(vec![[0., 0., 1.], [1., 0., 0.]])).unwrap();
+        cloud.set_attribute("energy", AttributeData::ScalarF64(vec![0.5, 1e-12])).unwrap();
+        cloud.set_attribute("label", AttributeData::Label(vec![7, 42])).unwrap();
+        assert!(cloud.set_attribute("short", AttributeData::ScalarF32(vec![1.])).is_err(), "Wrong length accepted");
+
+        let test_path = std::env::temp_dir().join("ps_data_layer_attributes.bin");
+        assert_eq!(2, cloud.write_to_file(&test_path).expect("Writing failed"));
+
+        let read_cloud = PointCloud::new_from_file(&test_path).expect("Reading failed");
+        assert_eq!(cloud.points, read_cloud.points);
+        assert_eq!(cloud.attributes, read_cloud.attributes);
+        assert_eq!(vec!["color", "normal", "energy", "label"], read_cloud.attribute_names());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        let mut cloud = PointCloud::new(vec![Point3::new(1., 2., 3.), Point3::new(-1., 0.5, 0.)]);
+        cloud.set_attribute("label", AttributeData::Label(vec![7, 8])).unwrap();
+
+        let json = serde_json::to_string(&cloud).unwrap();
+        assert!(json.starts_with(r#"{"points":[[1.0,2.0,3.0],[-1.0,0.5,0.0]]"#), "Unexpected {}", json);
+
+        let read_cloud: PointCloud = serde_json::from_str(&json).unwrap();
+        assert_eq!(cloud.points, read_cloud.points);
+        assert_eq!(cloud.attributes, read_cloud.attributes);
+    }
 }
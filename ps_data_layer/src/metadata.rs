@@ -0,0 +1,160 @@
+// Provenance metadata, stored in a JSON sidecar next to the saved file:
+// maps/points.bin gets maps/points.bin.meta.json.
+// The sidecar records the tool version, the commands that produced the cloud, the
+// random seed, creation and save times (UTC) and SHA-256 hashes of the saved file
+// and of the file it was derived from, if any.
+
+use crate::atomic::{self, WriteOptions};
+use crate::error::{Error, Result};
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const SIDECAR_EXTENSION: &str = "meta.json";
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandRecord {
+    pub command: String,
+    pub time: String,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub tool_version: String,
+    pub created: String,
+    pub saved: Option<String>,
+    pub seed: Option<u64>,
+    pub commands: Vec<CommandRecord>,
+    // Hash of the file this cloud was loaded from, before the commands.
+    pub parent_hash: Option<String>,
+    // Hash of the saved file, filled when saving.
+    pub content_hash: Option<String>,
+}
+
+impl Metadata {
+    pub fn new(i_tool_version: &str) -> Metadata {
+        Metadata {
+            tool_version: i_tool_version.to_string(),
+            created: utc_now(),
+            saved: None,
+            seed: None,
+            commands: Vec::new(),
+            parent_hash: None,
+            content_hash: None,
+        }
+    }
+
+    pub fn record(&mut self, i_command: &str) {
+        self.commands.push(CommandRecord { command: i_command.to_string(), time: utc_now() });
+    }
+}
+
+
+pub fn sidecar_path(i_path: &Path) -> PathBuf {
+    let mut name = i_path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(SIDECAR_EXTENSION);
+    PathBuf::from(name)
+}
+
+
+// None when the file has no sidecar.
+pub fn read_sidecar(i_path: &Path) -> Result<Option<Metadata>> {
+    let sidecar = sidecar_path(i_path);
+    if !sidecar.exists() {
+        return Ok(None);
+    }
+
+    let text = std::fs::read_to_string(&sidecar)?;
+    serde_json::from_str(&text)
+        .map(Some)
+        .map_err(|e| Error::Malformed(format!("{}: {}", sidecar.display(), e)))
+}
+
+
+pub fn write_sidecar(i_path: &Path, i_metadata: &Metadata) -> Result<()> {
+    let text = serde_json::to_string_pretty(i_metadata)
+        .map_err(|e| Error::InvalidInput(e.to_string()))?;
+    atomic::write_atomically(&sidecar_path(i_path), &WriteOptions::default(), |writer| {
+        writer.write_all(text.as_bytes())?;
+        Ok(())
+    })
+}
+
+
+// Hex SHA-256 of the file content.
+pub fn file_hash(i_path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(i_path)?, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+
+// Current time as ISO 8601, e.g. 2024-05-01T13:45:00Z.
+pub fn utc_now() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    utc_string(seconds)
+}
+
+
+fn utc_string(seconds: u64) -> String {
+    let (days, seconds) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Days to civil date, from Howard Hinnant's algorithm.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PointCloud;
+    use crate::Point3;
+
+    #[test]
+    fn utc_string_test() {
+        assert_eq!("1970-01-01T00:00:00Z", utc_string(0));
+        assert_eq!("2000-02-29T23:59:59Z", utc_string(951868799));
+        assert_eq!("2024-05-01T13:45:00Z", utc_string(1714571100));
+    }
+
+    #[test]
+    fn sidecar_test() {
+        let test_path = std::env::temp_dir().join("ps_data_layer_metadata.bin");
+        let _ = std::fs::remove_file(sidecar_path(&test_path));
+
+        let mut cloud = PointCloud::new(vec![Point3::new(1., 2., 3.)]);
+        cloud.write_to_file(&test_path).unwrap();
+        assert_eq!(None, PointCloud::new_from_file(&test_path).unwrap().metadata);
+
+        let mut metadata = Metadata::new("1.0");
+        metadata.seed = Some(42);
+        metadata.record("create cube --side 2");
+        cloud.metadata = Some(metadata.clone());
+        cloud.write_to_file(&test_path).unwrap();
+
+        let loaded = PointCloud::new_from_file(&test_path).unwrap().metadata.expect("Missing metadata");
+        assert_eq!(metadata.commands, loaded.commands);
+        assert_eq!(Some(42), loaded.seed);
+        assert!(loaded.saved.is_some());
+        assert_eq!(Some(file_hash(&test_path).unwrap()), loaded.content_hash);
+        assert_eq!(64, loaded.content_hash.unwrap().len());
+    }
+}
@@ -0,0 +1,481 @@
+// NumPy .npy and .npz import and export.
+// .npy files hold the positions only, as an (N, 3) float32 array.
+// .npz archives hold the positions in points.npy and one array per attribute channel,
+// named after the channel:
+//   color                 (N, 3) uint8
+//   normal                (N, 3) float32
+//   scalar f32 / f64      (N,) float32 / float64
+//   label                 (N,) uint32
+// When reading, (N, 3) float64 positions are narrowed to f32. Attribute arrays map
+// back by type: (N,) unsigned integers and booleans to labels, other (N,) numbers to
+// f64 scalars (f32 for float32), (N, 3) uint8 to colors, (N, 3) float32 to normals,
+// and other (N, k) arrays to k scalar channels name_0 .. name_k-1.
+// Both C and Fortran ordered arrays are read, written arrays are C ordered.
+
+use crate::attributes::{Attribute, AttributeData};
+use crate::error::{Error, Result};
+use crate::{Point3, PointCloud};
+
+use std::fs::File;
+use std::path::Path;
+use std::io::{Read, Write};
+use std::io::{BufReader, BufWriter};
+
+use zip::ZipArchive;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+const POINTS_ENTRY: &str = "points.npy";
+
+
+// Element types, with their size in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dtype {
+    Bool,
+    U1,
+    U2,
+    U4,
+    U8,
+    I1,
+    I2,
+    I4,
+    I8,
+    F4,
+    F8,
+}
+
+impl Dtype {
+    fn from_descr(descr: &str) -> Option<(Dtype, bool)> {
+        let (order, code) = descr.split_at(1);
+        let big_endian = match order {
+            "<" | "|" | "=" => false,
+            ">" => true,
+            _ => return None,
+        };
+        let dtype = match code {
+            "b1" => Dtype::Bool,
+            "u1" => Dtype::U1,
+            "u2" => Dtype::U2,
+            "u4" => Dtype::U4,
+            "u8" => Dtype::U8,
+            "i1" => Dtype::I1,
+            "i2" => Dtype::I2,
+            "i4" => Dtype::I4,
+            "i8" => Dtype::I8,
+            "f4" => Dtype::F4,
+            "f8" => Dtype::F8,
+            _ => return None,
+        };
+        Some((dtype, big_endian))
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Dtype::Bool | Dtype::U1 | Dtype::I1 => 1,
+            Dtype::U2 | Dtype::I2 => 2,
+            Dtype::U4 | Dtype::I4 | Dtype::F4 => 4,
+            Dtype::U8 | Dtype::I8 | Dtype::F8 => 8,
+        }
+    }
+
+    fn is_unsigned(&self) -> bool {
+        matches!(self, Dtype::Bool | Dtype::U1 | Dtype::U2 | Dtype::U4)
+    }
+}
+
+
+struct NpyArray {
+    dtype: Dtype,
+    shape: Vec<usize>,
+    // Values in C order, widened to f64 (exact for every type but 64 bits integers).
+    values: Vec<f64>,
+}
+
+impl NpyArray {
+    fn rows(&self) -> usize {
+        self.shape.first().copied().unwrap_or(1)
+    }
+
+    fn columns(&self) -> usize {
+        self.shape.iter().skip(1).product()
+    }
+
+    fn column(&self, column: usize) -> impl Iterator<Item = f64> + '_ {
+        let columns = self.columns();
+        (0..self.rows()).map(move |row| self.values[row * columns + column])
+    }
+}
+
+
+// Reading
+
+pub fn read_npy(file_path: &Path) -> Result<PointCloud> {
+    let array = read_array(&mut BufReader::new(File::open(file_path)?))?;
+    Ok(PointCloud::new(points_from_array(&array)?))
+}
+
+
+pub fn read_npz(file_path: &Path) -> Result<PointCloud> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(file_path)?)).map_err(zip_error)?;
+
+    let mut points = None;
+    let mut arrays = Vec::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(zip_error)?;
+        let Some(name) = entry.name().strip_suffix(".npy").map(str::to_string) else {
+            continue;
+        };
+        let array = read_array(&mut entry)?;
+        if name == "points" {
+            points = Some(points_from_array(&array)?);
+        } else {
+            arrays.push((name, array));
+        }
+    }
+
+    let points = points.ok_or_else(|| parse_error(format!("no {} array in the archive", POINTS_ENTRY)))?;
+    let mut cloud = PointCloud::new(points);
+    for (name, array) in arrays {
+        if array.rows() != cloud.points.len() {
+            return Err(parse_error(format!("array {} has {} rows for {} points", name, array.rows(), cloud.points.len())));
+        }
+        cloud.attributes.extend(attributes_from_array(&name, &array));
+    }
+
+    Ok(cloud)
+}
+
+
+fn points_from_array(array: &NpyArray) -> Result<Vec<Point3<f32>>> {
+    if array.shape.len() != 2 || array.shape[1] != 3 || !matches!(array.dtype, Dtype::F4 | Dtype::F8) {
+        return Err(parse_error(format!("positions must be an (N, 3) float array, found {:?} {:?}", array.shape, array.dtype)));
+    }
+    Ok(array.values.chunks_exact(3).map(|xyz| Point3::new(xyz[0] as f32, xyz[1] as f32, xyz[2] as f32)).collect())
+}
+
+
+fn attributes_from_array(name: &str, array: &NpyArray) -> Vec<Attribute> {
+    fn vectors<T>(values: &[f64], convert: impl Fn(f64) -> T) -> Vec<[T; 3]> {
+        values.chunks_exact(3).map(|xyz| [convert(xyz[0]), convert(xyz[1]), convert(xyz[2])]).collect()
+    }
+
+    match (array.shape.len(), array.dtype) {
+        (2, Dtype::U1) if array.shape[1] == 3 => vec![Attribute::new(name, AttributeData::Color(vectors(&array.values, |value| value as u8)))],
+        (2, Dtype::F4) if array.shape[1] == 3 => vec![Attribute::new(name, AttributeData::Normal(vectors(&array.values, |value| value as f32)))],
+        (1, _) => vec![Attribute::new(name, scalar_data(array.dtype, array.column(0)))],
+        _ => (0..array.columns())
+            .map(|column| Attribute::new(&format!("{}_{}", name, column), scalar_data(array.dtype, array.column(column))))
+            .collect(),
+    }
+}
+
+
+fn scalar_data(dtype: Dtype, values: impl Iterator<Item = f64>) -> AttributeData {
+    match dtype {
+        Dtype::F4 => AttributeData::ScalarF32(values.map(|value| value as f32).collect()),
+        _ if dtype.is_unsigned() => AttributeData::Label(values.map(|value| value as u32).collect()),
+        _ => AttributeData::ScalarF64(values.collect()),
+    }
+}
+
+
+fn read_array<R: Read>(reader: &mut R) -> Result<NpyArray> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[0..6] != MAGIC {
+        return Err(parse_error("not a .npy array".to_string()));
+    }
+
+    // Version 1 has a u16 header length, versions 2 and 3 a u32 one.
+    let header_length = match preamble[6] {
+        1 => {
+            let mut length = [0u8; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_le_bytes(length) as usize
+        }
+        2 | 3 => {
+            let mut length = [0u8; 4];
+            reader.read_exact(&mut length)?;
+            u32::from_le_bytes(length) as usize
+        }
+        version => return Err(Error::UnsupportedVersion(version as u16)),
+    };
+    let mut header = vec![0u8; header_length];
+    reader.read_exact(&mut header)?;
+    let header = String::from_utf8_lossy(&header);
+
+    let descr = header_value(&header, "descr")?;
+    let descr = descr.trim_matches(|c| c == '\'' || c == '"');
+    let (dtype, big_endian) = Dtype::from_descr(descr)
+        .ok_or_else(|| Error::Unsupported(format!("array type {}", descr)))?;
+    let fortran_order = header_value(&header, "fortran_order")? == "True";
+    let shape = header_value(&header, "shape")?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dimension| !dimension.is_empty())
+        .map(|dimension| dimension.parse().map_err(|_| parse_error(format!("bad shape dimension {}", dimension))))
+        .collect::<Result<Vec<usize>>>()?;
+
+    let size = shape.iter()
+        .try_fold(dtype.size(), |size, &dimension| size.checked_mul(dimension))
+        .ok_or_else(|| parse_error(format!("array of shape {:?} is too large", shape)))?;
+    let mut data = Vec::new();
+    reader.take(size as u64).read_to_end(&mut data)?;
+    if data.len() != size {
+        return Err(Error::Truncated { expected: size as u64, found: data.len() as u64 });
+    }
+
+    let mut values: Vec<f64> = data.chunks_exact(dtype.size()).map(|bytes| decode_value(dtype, big_endian, bytes)).collect();
+    if fortran_order && shape.len() > 1 {
+        values = fortran_to_c_order(&values, &shape);
+    }
+
+    Ok(NpyArray { dtype, shape, values })
+}
+
+
+// Raw text of a key in the header dictionary, e.g. 'shape': (3, 3).
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str> {
+    let missing = || parse_error(format!("no {} in the array header", key));
+    let start = header.find(&format!("'{}'", key)).ok_or_else(missing)? + key.len() + 2;
+    let rest = header[start..].trim_start().strip_prefix(':').ok_or_else(missing)?.trim_start();
+
+    // Up to the next comma outside parentheses.
+    let mut depth = 0;
+    let end = rest.char_indices()
+        .find(|&(_, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            depth == 0 && (c == ',' || c == '}')
+        })
+        .map(|(index, _)| index)
+        .unwrap_or(rest.len());
+    Ok(rest[..end].trim())
+}
+
+
+fn decode_value(dtype: Dtype, big_endian: bool, bytes: &[u8]) -> f64 {
+    let mut buffer = [0u8; 8];
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    if big_endian {
+        buffer[..bytes.len()].reverse();
+    }
+    match dtype {
+        Dtype::Bool | Dtype::U1 => buffer[0] as f64,
+        Dtype::I1 => buffer[0] as i8 as f64,
+        Dtype::U2 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+        Dtype::I2 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+        Dtype::U4 => u32::from_le_bytes(buffer[0..4].try_into().unwrap()) as f64,
+        Dtype::I4 => i32::from_le_bytes(buffer[0..4].try_into().unwrap()) as f64,
+        Dtype::F4 => f32::from_le_bytes(buffer[0..4].try_into().unwrap()) as f64,
+        Dtype::U8 => u64::from_le_bytes(buffer) as f64,
+        Dtype::I8 => i64::from_le_bytes(buffer) as f64,
+        Dtype::F8 => f64::from_le_bytes(buffer),
+    }
+}
+
+
+fn fortran_to_c_order(values: &[f64], shape: &[usize]) -> Vec<f64> {
+    let mut result = vec![0.; values.len()];
+    let mut index = vec![0usize; shape.len()];
+    for &value in values {
+        // Fortran order: the first index moves fastest.
+        let c_offset = index.iter().zip(shape).fold(0, |offset, (&i, &dimension)| offset * dimension + i);
+        result[c_offset] = value;
+        for (i, &dimension) in index.iter_mut().zip(shape) {
+            *i += 1;
+            if *i < dimension {
+                break;
+            }
+            *i = 0;
+        }
+    }
+    result
+}
+
+
+// Writing
+
+pub fn write_npy(file_path: &Path, cloud: &PointCloud) -> Result<usize> {
+    if !cloud.attributes.is_empty() {
+        println!("Warning: .npy files hold the positions only, use .npz to keep the attributes");
+    }
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write_points_array(&mut writer, &cloud.points)?;
+    writer.flush()?;
+    Ok(cloud.points.len())
+}
+
+
+pub fn write_npz(file_path: &Path, cloud: &PointCloud) -> Result<usize> {
+    if cloud.attributes.iter().any(|attribute| attribute.name == "points") {
+        return Err(Error::InvalidInput("an attribute named points would replace the positions".to_string()));
+    }
+
+    let mut archive = ZipWriter::new(BufWriter::new(File::create(file_path)?));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    archive.start_file(POINTS_ENTRY, options).map_err(zip_error)?;
+    write_points_array(&mut archive, &cloud.points)?;
+
+    for attribute in &cloud.attributes {
+        archive.start_file(format!("{}.npy", attribute.name), options).map_err(zip_error)?;
+        write_attribute_array(&mut archive, &attribute.data)?;
+    }
+
+    archive.finish().map_err(zip_error)?.flush()?;
+    Ok(cloud.points.len())
+}
+
+
+fn write_points_array<W: Write>(writer: &mut W, points: &[Point3<f32>]) -> Result<()> {
+    write_array_header(writer, "<f4", &[points.len(), 3])?;
+    for point in points {
+        for value in [point.x, point.y, point.z] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+
+fn write_attribute_array<W: Write>(writer: &mut W, data: &AttributeData) -> Result<()> {
+    match data {
+        AttributeData::Color(values) => {
+            write_array_header(writer, "|u1", &[values.len(), 3])?;
+            for value in values {
+                writer.write_all(value)?;
+            }
+        }
+        AttributeData::Normal(values) => {
+            write_array_header(writer, "<f4", &[values.len(), 3])?;
+            for value in values.iter().flatten() {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        AttributeData::ScalarF32(values) => {
+            write_array_header(writer, "<f4", &[values.len()])?;
+            for value in values {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        AttributeData::ScalarF64(values) => {
+            write_array_header(writer, "<f8", &[values.len()])?;
+            for value in values {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        AttributeData::Label(values) => {
+            write_array_header(writer, "<u4", &[values.len()])?;
+            for value in values {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// Version 1 header, padded so that the data starts on a 64 bytes boundary.
+fn write_array_header<W: Write>(writer: &mut W, descr: &str, shape: &[usize]) -> Result<()> {
+    let shape = match shape {
+        [single] => format!("({},)", single),
+        _ => format!("({})", shape.iter().map(|dimension| dimension.to_string()).collect::<Vec<_>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    let total = MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat(total.next_multiple_of(64) - total));
+    header.push('\n');
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    Ok(())
+}
+
+
+fn zip_error(error: zip::result::ZipError) -> Error {
+    match error {
+        zip::result::ZipError::Io(error) => Error::Io(error),
+        other => Error::Malformed(other.to_string()),
+    }
+}
+
+
+fn parse_error(message: String) -> Error {
+    Error::Malformed(message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn npz_round_trip_test() {
+        let test_path = std::env::temp_dir().join("ps_data_layer_cloud.npz");
+
+        let mut cloud = PointCloud::new(vec![Point3::new(1., 2., 3.), Point3::new(-4., 5.5, 6.)]);
+        cloud.set_attribute("color", AttributeData::Color(vec![[255, 0, 0], [0, 128, 255]])).unwrap();
+        cloud.set_attribute("normal", AttributeData::Normal(vec![[0., 0., 1.], [1., 0., 0.]])).unwrap();
+        cloud.set_attribute("intensity", AttributeData::ScalarF32(vec![0.5, 0.25])).unwrap();
+        cloud.set_attribute("energy", AttributeData::ScalarF64(vec![1e-300, 2.])).unwrap();
+        cloud.set_attribute("label", AttributeData::Label(vec![7, u32::MAX])).unwrap();
+
+        assert_eq!(2, write_npz(&test_path, &cloud).expect("Writing failed"));
+        let read_cloud = read_npz(&test_path).expect("Reading failed");
+        assert_eq!(cloud.points, read_cloud.points);
+        assert_eq!(cloud.attributes, read_cloud.attributes);
+
+        // Rejected before anything is written.
+        let rejected_path = std::env::temp_dir().join("ps_data_layer_rejected.npz");
+        let _ = std::fs::remove_file(&rejected_path);
+        cloud.set_attribute("points", AttributeData::Label(vec![0, 1])).unwrap();
+        assert!(write_npz(&rejected_path, &cloud).is_err());
+        assert!(!rejected_path.exists());
+
+        let test_path = std::env::temp_dir().join("ps_data_layer_cloud.npy");
+        write_npy(&test_path, &cloud).unwrap();
+        let bytes = std::fs::read(&test_path).unwrap();
+        assert_eq!(0, (bytes.len() - 2 * 12) % 64, "Data not aligned");
+        assert_eq!(cloud.points, read_npy(&test_path).unwrap().points);
+    }
+
+    #[test]
+    fn npy_layouts_test() {
+        // Big endian float64 positions in Fortran order, as numpy may write them.
+        let header = "{'descr': '>f8', 'fortran_order': True, 'shape': (2, 3), }";
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        for value in [1f64, 4., 2., 5., 3., 6.] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let array = read_array(&mut &bytes[..]).unwrap();
+        assert_eq!(vec![2, 3], array.shape);
+        assert_eq!(vec![Point3::new(1., 2., 3.), Point3::new(4., 5., 6.)], points_from_array(&array).unwrap());
+
+        // Other (N, k) arrays become one channel per column.
+        let attributes = attributes_from_array("weights", &array);
+        assert_eq!(vec!["weights_0", "weights_1", "weights_2"], attributes.iter().map(|attribute| attribute.name.as_str()).collect::<Vec<_>>());
+        assert_eq!(AttributeData::ScalarF64(vec![2., 5.]), attributes[1].data);
+
+        assert!(read_array(&mut &bytes[..bytes.len() - 1]).is_err());
+
+        // Shapes overflowing the array size.
+        let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (4294967296, 4294967296), }";
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        assert!(matches!(read_array(&mut &bytes[..]), Err(Error::Malformed(_))));
+    }
+}
@@ -0,0 +1,204 @@
+// Wavefront OBJ import and export.
+// Written elements:
+//   v x y z [r g b]   vertices, with colors in 0..1 when given (a common extension)
+//   l a b             line elements, e.g. the connections between points
+//   f a b c ...       faces
+// Indices are 0-based in ObjData and 1-based in the file.
+// Reading keeps the vertices (and their colors, when every vertex has one), the lines
+// and the faces; texture coordinates, normals and groups are ignored. Face and line
+// indices may be negative (relative to the last vertex) and may carry /vt/vn parts.
+
+use crate::attributes::AttributeData;
+use crate::error::{Error, Result};
+use crate::{AttributeKind, Point3, PointCloud};
+
+use std::fs::File;
+use std::path::Path;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjData {
+    pub vertices: Vec<[f64; 3]>,
+    pub colors: Option<Vec<[u8; 3]>>,
+    pub lines: Vec<[usize; 2]>,
+    pub faces: Vec<Vec<usize>>,
+}
+
+
+// Reading
+
+pub fn read_obj(file_path: &Path) -> Result<PointCloud> {
+    let data = read_obj_data(file_path)?;
+    let mut cloud = PointCloud::new(data.vertices.iter()
+        .map(|vertex| Point3::new(vertex[0] as f32, vertex[1] as f32, vertex[2] as f32))
+        .collect());
+    if let Some(colors) = data.colors {
+        cloud.set_attribute("color", AttributeData::Color(colors))?;
+    }
+    Ok(cloud)
+}
+
+
+pub fn read_obj_data(file_path: &Path) -> Result<ObjData> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut data = ObjData::default();
+    let mut colors = Vec::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        let error = |message: &str| parse_error(format!("line {}: {}", number + 1, message));
+
+        match tokens.next() {
+            Some("v") => {
+                let values = tokens
+                    .map(|token| token.parse::<f64>().map_err(|_| error(&format!("bad vertex value {}", token))))
+                    .collect::<Result<Vec<f64>>>()?;
+                if values.len() < 3 {
+                    return Err(error("vertex with less than 3 coordinates"));
+                }
+                data.vertices.push([values[0], values[1], values[2]]);
+                // x y z r g b, not x y z w
+                if values.len() >= 6 {
+                    colors.push([color_byte(values[3]), color_byte(values[4]), color_byte(values[5])]);
+                }
+            }
+            Some("l") => {
+                let indices = tokens
+                    .map(|token| vertex_index(token, data.vertices.len()).ok_or_else(|| error(&format!("bad index {}", token))))
+                    .collect::<Result<Vec<usize>>>()?;
+                // Polylines are split in segments.
+                data.lines.extend(indices.windows(2).map(|pair| [pair[0], pair[1]]));
+            }
+            Some("f") => {
+                let indices = tokens
+                    .map(|token| vertex_index(token, data.vertices.len()).ok_or_else(|| error(&format!("bad index {}", token))))
+                    .collect::<Result<Vec<usize>>>()?;
+                if indices.len() < 3 {
+                    return Err(error("face with less than 3 vertices"));
+                }
+                data.faces.push(indices);
+            }
+            _ => {}
+        }
+    }
+
+    if !colors.is_empty() && colors.len() == data.vertices.len() {
+        data.colors = Some(colors);
+    }
+    Ok(data)
+}
+
+
+// 1-based or negative index, possibly followed by /vt/vn.
+fn vertex_index(token: &str, vertex_count: usize) -> Option<usize> {
+    let index: i64 = token.split('/').next()?.parse().ok()?;
+    let index = match index {
+        0 => return None,
+        index if index > 0 => index as usize - 1,
+        index => vertex_count.checked_sub(index.unsigned_abs() as usize)?,
+    };
+    (index < vertex_count).then_some(index)
+}
+
+
+fn color_byte(value: f64) -> u8 {
+    (value.clamp(0., 1.) * 255.).round() as u8
+}
+
+
+// Writing
+
+pub fn write_obj(file_path: &Path, data: &ObjData) -> Result<usize> {
+    if let Some(colors) = &data.colors {
+        if colors.len() != data.vertices.len() {
+            return Err(Error::InvalidInput(format!("{} colors for {} vertices", colors.len(), data.vertices.len())));
+        }
+    }
+    let vertex_count = data.vertices.len();
+    if data.lines.iter().flatten().chain(data.faces.iter().flatten()).any(|&index| index >= vertex_count) {
+        return Err(Error::InvalidInput(format!("element referencing a vertex out of {} vertices", vertex_count)));
+    }
+
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    writeln!(writer, "# {} vertices, {} lines, {} faces", vertex_count, data.lines.len(), data.faces.len())?;
+
+    for (index, vertex) in data.vertices.iter().enumerate() {
+        write!(writer, "v {} {} {}", vertex[0], vertex[1], vertex[2])?;
+        if let Some(colors) = &data.colors {
+            let [r, g, b] = colors[index];
+            write!(writer, " {:.4} {:.4} {:.4}", r as f64 / 255., g as f64 / 255., b as f64 / 255.)?;
+        }
+        writeln!(writer)?;
+    }
+    for [a, b] in &data.lines {
+        writeln!(writer, "l {} {}", a + 1, b + 1)?;
+    }
+    for face in &data.faces {
+        write!(writer, "f")?;
+        for index in face {
+            write!(writer, " {}", index + 1)?;
+        }
+        writeln!(writer)?;
+    }
+
+    writer.flush()?;
+    Ok(vertex_count)
+}
+
+
+// Vertices of the cloud, with the first color channel if any.
+pub fn write_cloud_obj(file_path: &Path, cloud: &PointCloud) -> Result<usize> {
+    let colors = match cloud.find_attribute(AttributeKind::Color).map(|attribute| &attribute.data) {
+        Some(AttributeData::Color(colors)) => Some(colors.clone()),
+        _ => None,
+    };
+    if cloud.attributes.len() > colors.is_some() as usize {
+        println!("Warning: .obj files hold the positions and colors only, the other attributes are not saved");
+    }
+    let data = ObjData {
+        vertices: cloud.points.iter().map(|point| [point.x as f64, point.y as f64, point.z as f64]).collect(),
+        colors,
+        ..ObjData::default()
+    };
+    write_obj(file_path, &data)
+}
+
+
+fn parse_error(message: String) -> Error {
+    Error::Malformed(message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj_round_trip_test() {
+        let test_path = std::env::temp_dir().join("ps_data_layer_mesh.obj");
+        let data = ObjData {
+            vertices: vec![[0., 0., 0.], [1., 0., 0.], [0., 1., 0.], [0.1, 0.2, 1. / 3.]],
+            colors: Some(vec![[255, 0, 0], [0, 255, 0], [0, 0, 255], [51, 102, 153]]),
+            lines: vec![[0, 3], [1, 3]],
+            faces: vec![vec![0, 1, 2]],
+        };
+        assert_eq!(4, write_obj(&test_path, &data).expect("Writing failed"));
+        assert_eq!(data, read_obj_data(&test_path).expect("Reading failed"));
+
+        let cloud = read_obj(&test_path).unwrap();
+        assert_eq!(Point3::new(0.1, 0.2, 1. / 3.), cloud.points[3]);
+        assert_eq!(Some(&AttributeData::Color(data.colors.clone().unwrap())), cloud.get_attribute("color"));
+
+        // Elements from other tools: texture and normal indices, negative indices, polylines.
+        std::fs::write(&test_path, "o mesh\nv 0 0 0\nv 1 0 0\nv 0 1 0 1.0\nvn 0 0 1\nf 1/1/1 2//1 -1\nl 1 2 3\n").unwrap();
+        let read_data = read_obj_data(&test_path).unwrap();
+        assert_eq!(None, read_data.colors);
+        assert_eq!(vec![vec![0, 1, 2]], read_data.faces);
+        assert_eq!(vec![[0, 1], [1, 2]], read_data.lines);
+
+        std::fs::write(&test_path, "v 0 0 0\nf 1 2 3\n").unwrap();
+        assert!(matches!(read_obj_data(&test_path), Err(Error::Malformed(_))));
+    }
+}
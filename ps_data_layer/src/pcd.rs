@@ -0,0 +1,470 @@
+// PCD (Point Cloud Library) import and export, ASCII and binary data.
+// FIELDS / SIZE / TYPE / COUNT are honored and mapped to attribute channels:
+//   x, y, z                          -> positions
+//   normal_x, normal_y, normal_z     -> Normal "normal"
+//   rgb, rgba (packed 0x00RRGGBB)    -> Color "color"
+//   intensity                        -> ScalarF32 "intensity"
+//   label                            -> Label "label"
+// Any other field is preserved as an opaque scalar channel named after the field
+// (<field>_<i> when COUNT is above 1): F4 -> ScalarF32, F8 -> ScalarF64,
+// unsigned up to 4 bytes -> Label, anything else -> ScalarF64.
+// On export, consecutive scalar channels <field>_0, <field>_1.. are written back as a
+// single field with that COUNT.
+// Padding fields named "_" are skipped. binary_compressed data is not supported.
+
+use crate::{Attribute, AttributeData, AttributeKind, PointCloud};
+use kiss3d::nalgebra::Point3;
+
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+use std::io::Seek;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::io::BufWriter;
+use crate::error::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcdFormat {
+    Ascii,
+    Binary,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldType {
+    Float,
+    Unsigned,
+    Signed,
+}
+
+#[derive(Debug, Clone)]
+struct PcdField {
+    name: String,
+    size: usize,
+    field_type: FieldType,
+    count: usize,
+}
+
+impl PcdField {
+    // Exactly representable in a f64, except for 8 byte integers.
+    fn decode(&self, bytes: &[u8]) -> f64 {
+        match (self.field_type, self.size) {
+            (FieldType::Float, 4) => f32::from_le_bytes(bytes[0..4].try_into().unwrap()) as f64,
+            (FieldType::Float, _) => f64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            (FieldType::Unsigned, 1) => bytes[0] as f64,
+            (FieldType::Unsigned, 2) => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            (FieldType::Unsigned, 4) => u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as f64,
+            (FieldType::Unsigned, _) => u64::from_le_bytes(bytes[0..8].try_into().unwrap()) as f64,
+            (FieldType::Signed, 1) => bytes[0] as i8 as f64,
+            (FieldType::Signed, 2) => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            (FieldType::Signed, 4) => i32::from_le_bytes(bytes[0..4].try_into().unwrap()) as f64,
+            (FieldType::Signed, _) => i64::from_le_bytes(bytes[0..8].try_into().unwrap()) as f64,
+        }
+    }
+
+    fn parse(&self, token: &str) -> Option<f64> {
+        match (self.field_type, self.size) {
+            // Parsing single precision directly keeps packed colors bit exact.
+            (FieldType::Float, 4) => token.parse::<f32>().ok().map(|value| value as f64),
+            (FieldType::Float, _) => token.parse::<f64>().ok(),
+            (FieldType::Unsigned, _) => token.parse::<u64>().ok().map(|value| value as f64),
+            (FieldType::Signed, _) => token.parse::<i64>().ok().map(|value| value as f64),
+        }
+    }
+
+    // Packed colors come as the bits of a float or as an unsigned integer.
+    fn packed_color(&self, value: f64) -> [u8; 3] {
+        let bits = match self.field_type {
+            FieldType::Float => (value as f32).to_bits(),
+            _ => value as u32,
+        };
+        [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8]
+    }
+}
+
+
+// Reading
+
+pub fn read_pcd(file_path: &Path) -> Result<PointCloud> {
+    let file = File::open(file_path)?;
+    let mut reader = BufReader::new(file);
+    let (fields, point_count, format) = read_header(&mut reader)?;
+    let data_size = reader.get_ref().metadata()?.len().saturating_sub(reader.stream_position()?);
+
+    let column_count = fields.iter().try_fold(0usize, |total, field| total.checked_add(field.count));
+    let record_size = fields.iter()
+        .try_fold(0usize, |total, field| field.size.checked_mul(field.count).and_then(|size| total.checked_add(size)));
+    let (Some(column_count), Some(record_size)) = (column_count, record_size) else {
+        return Err(parse_error("pcd field counts are too large".to_string()));
+    };
+
+    // Every value takes at least a byte of data in both encodings, so the columns reserved below
+    // stay within the file size.
+    let value_size = if format == PcdFormat::Binary { record_size } else { column_count };
+    let expected = (point_count as u64).checked_mul(value_size as u64)
+        .ok_or_else(|| parse_error(format!("point count {} is too large", point_count)))?;
+    if data_size < expected {
+        return Err(Error::Truncated { expected, found: data_size });
+    }
+
+    // One column per field element.
+    let mut columns: Vec<Vec<f64>> = fields.iter()
+        .flat_map(|field| (0..field.count).map(|_| Vec::with_capacity(point_count)))
+        .collect();
+
+    match format {
+        PcdFormat::Ascii => {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+            for point_index in 0..point_count {
+                let line = lines.next()
+                    .ok_or_else(|| Error::Malformed(format!("pcd data ends at point {}", point_index)))?;
+                let mut tokens = line.split_whitespace();
+                let mut column = 0;
+                for field in &fields {
+                    for _ in 0..field.count {
+                        let token = tokens.next()
+                            .ok_or_else(|| parse_error(format!("point {}: missing values", point_index)))?;
+                        let value = field.parse(token)
+                            .ok_or_else(|| parse_error(format!("point {}: bad value {}", point_index, token)))?;
+                        columns[column].push(value);
+                        column += 1;
+                    }
+                }
+            }
+        }
+        PcdFormat::Binary => {
+            let mut record = vec![0u8; record_size];
+            for _ in 0..point_count {
+                reader.read_exact(&mut record)?;
+                let mut position = 0;
+                let mut column = 0;
+                for field in &fields {
+                    for _ in 0..field.count {
+                        columns[column].push(field.decode(&record[position..position + field.size]));
+                        position += field.size;
+                        column += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // Naming every column, then mapping the known ones.
+    let mut named: Vec<(String, &PcdField, Vec<f64>)> = Vec::new();
+    let mut columns = columns.into_iter();
+    for field in &fields {
+        for element in 0..field.count {
+            let name = if field.count == 1 { field.name.clone() } else { format!("{}_{}", field.name, element) };
+            named.push((name, field, columns.next().unwrap()));
+        }
+    }
+    named.retain(|(name, _, _)| name != "_" && !name.starts_with("__"));
+
+    let mut take_column = |name: &str| {
+        named.iter().position(|(column_name, _, _)| column_name == name).map(|index| named.remove(index))
+    };
+
+    let (Some(x), Some(y), Some(z)) = (take_column("x"), take_column("y"), take_column("z")) else {
+        return Err(parse_error("pcd without x, y, z fields".to_string()));
+    };
+    let points = (0..point_count).map(|index| Point3::new(x.2[index] as f32, y.2[index] as f32, z.2[index] as f32)).collect();
+    let mut cloud = PointCloud::new(points);
+
+    if let (Some(nx), Some(ny), Some(nz)) = (take_column("normal_x"), take_column("normal_y"), take_column("normal_z")) {
+        let normals = (0..point_count).map(|index| [nx.2[index] as f32, ny.2[index] as f32, nz.2[index] as f32]).collect();
+        cloud.attributes.push(Attribute::new("normal", AttributeData::Normal(normals)));
+    }
+    if let Some((_, field, values)) = take_column("rgb").or_else(|| take_column("rgba")) {
+        let colors = values.iter().map(|&value| field.packed_color(value)).collect();
+        cloud.attributes.push(Attribute::new("color", AttributeData::Color(colors)));
+    }
+    if let Some((_, _, values)) = take_column("intensity") {
+        cloud.attributes.push(Attribute::new("intensity", AttributeData::ScalarF32(values.into_iter().map(|value| value as f32).collect())));
+    }
+    if let Some((_, _, values)) = take_column("label") {
+        cloud.attributes.push(Attribute::new("label", AttributeData::Label(values.into_iter().map(|value| value as u32).collect())));
+    }
+
+    for (name, field, values) in named {
+        let data = match (field.field_type, field.size) {
+            (FieldType::Float, 4) => AttributeData::ScalarF32(values.into_iter().map(|value| value as f32).collect()),
+            (FieldType::Unsigned, size) if size <= 4 => AttributeData::Label(values.into_iter().map(|value| value as u32).collect()),
+            _ => AttributeData::ScalarF64(values),
+        };
+        cloud.attributes.push(Attribute::new(&name, data));
+    }
+
+    Ok(cloud)
+}
+
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(Vec<PcdField>, usize, PcdFormat)> {
+    let mut names: Vec<String> = Vec::new();
+    let mut sizes: Vec<usize> = Vec::new();
+    let mut types: Vec<FieldType> = Vec::new();
+    let mut counts: Vec<usize> = Vec::new();
+    let mut width = None;
+    let mut height = 1;
+    let mut points = None;
+
+    let mut line = String::new();
+    let format = loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(parse_error("pcd header without DATA line".to_string()));
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some((&key, values)) = tokens.split_first() else { continue };
+        match key.to_ascii_uppercase().as_str() {
+            "VERSION" | "VIEWPOINT" => {}
+            "FIELDS" | "COLUMNS" => names = values.iter().map(|name| name.to_string()).collect(),
+            "SIZE" => sizes = parse_numbers(values)?,
+            "COUNT" => counts = parse_numbers(values)?,
+            "TYPE" => types = values.iter().map(|value| match *value {
+                "F" => Ok(FieldType::Float),
+                "U" => Ok(FieldType::Unsigned),
+                "I" => Ok(FieldType::Signed),
+                other => Err(parse_error(format!("unknown field type {}", other))),
+            }).collect::<Result<_>>()?,
+            "WIDTH" => width = parse_numbers(values)?.first().copied(),
+            "HEIGHT" => height = parse_numbers(values)?.first().copied().unwrap_or(1),
+            "POINTS" => points = parse_numbers(values)?.first().copied(),
+            "DATA" => match values.first().copied() {
+                Some("ascii") => break PcdFormat::Ascii,
+                Some("binary") => break PcdFormat::Binary,
+                Some(other) => return Err(Error::Unsupported(format!("unsupported pcd data {}", other))),
+                None => return Err(parse_error("empty DATA line".to_string())),
+            },
+            _ if key.starts_with('#') => {}
+            other => return Err(parse_error(format!("unknown pcd header entry {}", other))),
+        }
+    };
+
+    if counts.is_empty() {
+        counts = vec![1; names.len()];
+    }
+    if sizes.len() != names.len() || types.len() != names.len() || counts.len() != names.len() {
+        return Err(parse_error("FIELDS, SIZE, TYPE and COUNT lengths differ".to_string()));
+    }
+
+    let mut fields = Vec::with_capacity(names.len());
+    for (((name, size), field_type), count) in names.into_iter().zip(sizes).zip(types).zip(counts) {
+        let valid_size = match field_type {
+            FieldType::Float => size == 4 || size == 8,
+            _ => matches!(size, 1 | 2 | 4 | 8),
+        };
+        if !valid_size {
+            return Err(parse_error(format!("field {} has invalid size {}", name, size)));
+        }
+        fields.push(PcdField { name, size, field_type, count });
+    }
+
+    let point_count = match (points, width) {
+        (Some(points), _) => points,
+        (None, Some(width)) => width.checked_mul(height)
+            .ok_or_else(|| parse_error(format!("WIDTH {} and HEIGHT {} are too large", width, height)))?,
+        (None, None) => return Err(parse_error("pcd header without POINTS or WIDTH".to_string())),
+    };
+    Ok((fields, point_count, format))
+}
+
+
+fn parse_numbers(values: &[&str]) -> Result<Vec<usize>> {
+    values.iter()
+        .map(|value| value.parse().map_err(|_| parse_error(format!("bad number {}", value))))
+        .collect()
+}
+
+
+// Writing
+
+pub fn write_pcd(file_path: &Path, cloud: &PointCloud, format: PcdFormat) -> Result<usize> {
+    if let Some(attribute) = cloud.attributes.iter().find(|attribute| attribute.data.len() != cloud.points.len()) {
+        return Err(Error::InvalidInput(format!(
+            "attribute {} has {} values for {} points", attribute.name, attribute.data.len(), cloud.points.len())));
+    }
+
+    // Field declarations: name, size, type, count
+    let mut declarations: Vec<(String, usize, char, usize)> = vec![
+        ("x".to_string(), 4, 'F', 1), ("y".to_string(), 4, 'F', 1), ("z".to_string(), 4, 'F', 1)];
+    let mut index = 0;
+    while index < cloud.attributes.len() {
+        // The values of a group are written in the channels order, as they come.
+        let (name, count) = match field_group(&cloud.attributes[index..]) {
+            Some((base, count)) => (base.to_string(), count),
+            None => (cloud.attributes[index].name.clone(), 1),
+        };
+        match &cloud.attributes[index].data {
+            AttributeData::Normal(_) if name == "normal" => declarations.extend([
+                ("normal_x".to_string(), 4, 'F', 1), ("normal_y".to_string(), 4, 'F', 1), ("normal_z".to_string(), 4, 'F', 1)]),
+            AttributeData::Normal(_) => declarations.extend([
+                (format!("{}_x", name), 4, 'F', 1), (format!("{}_y", name), 4, 'F', 1), (format!("{}_z", name), 4, 'F', 1)]),
+            AttributeData::Color(_) if name == "color" => declarations.push(("rgb".to_string(), 4, 'F', 1)),
+            AttributeData::Color(_) => declarations.push((name, 4, 'U', 1)),
+            AttributeData::ScalarF32(_) => declarations.push((name, 4, 'F', count)),
+            AttributeData::ScalarF64(_) => declarations.push((name, 8, 'F', count)),
+            AttributeData::Label(_) => declarations.push((name, 4, 'U', count)),
+        }
+        index += count;
+    }
+
+    let file = File::create(file_path)?;
+    let mut writer = BufWriter::new(file);
+
+    let join = |values: Vec<String>| values.join(" ");
+    writeln!(writer, "# .PCD v0.7 - Point Cloud Data file format")?;
+    writeln!(writer, "VERSION 0.7")?;
+    writeln!(writer, "FIELDS {}", join(declarations.iter().map(|declaration| declaration.0.clone()).collect()))?;
+    writeln!(writer, "SIZE {}", join(declarations.iter().map(|declaration| declaration.1.to_string()).collect()))?;
+    writeln!(writer, "TYPE {}", join(declarations.iter().map(|declaration| declaration.2.to_string()).collect()))?;
+    writeln!(writer, "COUNT {}", join(declarations.iter().map(|declaration| declaration.3.to_string()).collect()))?;
+    writeln!(writer, "WIDTH {}", cloud.points.len())?;
+    writeln!(writer, "HEIGHT 1")?;
+    writeln!(writer, "VIEWPOINT 0 0 0 1 0 0 0")?;
+    writeln!(writer, "POINTS {}", cloud.points.len())?;
+    match format {
+        PcdFormat::Ascii => writeln!(writer, "DATA ascii")?,
+        PcdFormat::Binary => writeln!(writer, "DATA binary")?,
+    }
+
+    let mut values: Vec<Value> = Vec::with_capacity(declarations.len());
+    for (index, point) in cloud.points.iter().enumerate() {
+        values.clear();
+        values.extend([Value::F32(point.x), Value::F32(point.y), Value::F32(point.z)]);
+        for attribute in &cloud.attributes {
+            match &attribute.data {
+                AttributeData::Normal(normals) => values.extend(normals[index].map(Value::F32)),
+                AttributeData::Color(colors) => {
+                    let [r, g, b] = colors[index];
+                    let packed = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+                    if attribute.name == "color" {
+                        values.push(Value::F32(f32::from_bits(packed)));
+                    } else {
+                        values.push(Value::U32(packed));
+                    }
+                }
+                AttributeData::ScalarF32(scalars) => values.push(Value::F32(scalars[index])),
+                AttributeData::ScalarF64(scalars) => values.push(Value::F64(scalars[index])),
+                AttributeData::Label(labels) => values.push(Value::U32(labels[index])),
+            }
+        }
+
+        match format {
+            PcdFormat::Ascii => {
+                let tokens: Vec<String> = values.iter().map(Value::to_string).collect();
+                writeln!(writer, "{}", tokens.join(" "))?;
+            }
+            PcdFormat::Binary => for value in &values {
+                match value {
+                    Value::F32(value) => writer.write_all(&value.to_le_bytes())?,
+                    Value::F64(value) => writer.write_all(&value.to_le_bytes())?,
+                    Value::U32(value) => writer.write_all(&value.to_le_bytes())?,
+                }
+            },
+        }
+    }
+
+    writer.flush()?;
+    Ok(cloud.points.len())
+}
+
+
+// The channels split from a COUNT > 1 field when reading, <field>_0, <field>_1.. with
+// the same scalar kind, at the start of the given ones: the field name and count.
+fn field_group(attributes: &[Attribute]) -> Option<(&str, usize)> {
+    let base = attributes[0].name.strip_suffix("_0")?;
+    let kind = attributes[0].data.kind();
+    if !matches!(kind, AttributeKind::ScalarF32 | AttributeKind::ScalarF64 | AttributeKind::Label) {
+        return None;
+    }
+    let count = attributes.iter().enumerate()
+        .take_while(|(element, attribute)| attribute.data.kind() == kind && attribute.name == format!("{}_{}", base, element))
+        .count();
+    (count > 1).then_some((base, count))
+}
+
+
+enum Value {
+    F32(f32),
+    F64(f64),
+    U32(u32),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::F32(value) => write!(formatter, "{}", value),
+            Value::F64(value) => write!(formatter, "{}", value),
+            Value::U32(value) => write!(formatter, "{}", value),
+        }
+    }
+}
+
+
+fn parse_error(message: String) -> Error {
+    Error::Malformed(message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pcd_round_trip_test() {
+        let mut cloud = PointCloud::new(vec![Point3::new(0.5, -1., 2.), Point3::new(3., 4., 5.25)]);
+        cloud.set_attribute("normal", AttributeData::Normal(vec![[0., 0., 1.], [0., 1., 0.]])).unwrap();
+        cloud.set_attribute("color", AttributeData::Color(vec![[255, 128, 0], [1, 2, 3]])).unwrap();
+        cloud.set_attribute("intensity", AttributeData::ScalarF32(vec![0.5, 0.75])).unwrap();
+        cloud.set_attribute("label", AttributeData::Label(vec![3, 4])).unwrap();
+        cloud.set_attribute("energy", AttributeData::ScalarF64(vec![1e-10, 2.])).unwrap();
+
+        for (format, file_name) in [(PcdFormat::Ascii, "ps_data_layer_ascii.pcd"), (PcdFormat::Binary, "ps_data_layer_binary.pcd")] {
+            let test_path = std::env::temp_dir().join(file_name);
+            assert_eq!(2, write_pcd(&test_path, &cloud, format).expect("Writing failed"));
+
+            let read_cloud = read_pcd(&test_path).expect("Reading failed");
+            assert_eq!(cloud.points, read_cloud.points, "Positions mismatch for {:?}", format);
+            assert_eq!(cloud.attributes, read_cloud.attributes, "Attributes mismatch for {:?}", format);
+        }
+    }
+
+    #[test]
+    fn pcd_unknown_fields_test() {
+        let text = "# .PCD v0.7\nVERSION 0.7\nFIELDS x y z rgba curvature histogram _\n\
+            SIZE 4 4 4 4 4 2 1\nTYPE F F F U F I U\nCOUNT 1 1 1 1 1 2 1\n\
+            WIDTH 2\nHEIGHT 1\nPOINTS 2\nDATA ascii\n\
+            1 2 3 16711680 0.5 -1 7 0\n4 5 6 255 0.25 3 -4 0\n";
+        let test_path = std::env::temp_dir().join("ps_data_layer_unknown.pcd");
+        std::fs::write(&test_path, text).unwrap();
+
+        let cloud = read_pcd(&test_path).expect("Reading failed");
+        assert_eq!(vec!["color", "curvature", "histogram_0", "histogram_1"], cloud.attribute_names());
+        assert_eq!(Some(&AttributeData::Color(vec![[255, 0, 0], [0, 0, 255]])), cloud.get_attribute("color"));
+        assert_eq!(Some(&AttributeData::ScalarF64(vec![7., -4.])), cloud.get_attribute("histogram_1"));
+
+        // The histogram elements go back to a single field.
+        let written_path = std::env::temp_dir().join("ps_data_layer_unknown_written.pcd");
+        write_pcd(&written_path, &cloud, PcdFormat::Binary).unwrap();
+        let (fields, _, _) = read_header(&mut BufReader::new(File::open(&written_path).unwrap())).unwrap();
+        assert_eq!(Some(2), fields.iter().find(|field| field.name == "histogram").map(|field| field.count));
+        assert_eq!(cloud.attributes, read_pcd(&written_path).unwrap().attributes);
+
+        // Binary data shorter than the point count is rejected upfront.
+        let bytes = std::fs::read(&written_path).unwrap();
+        std::fs::write(&written_path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(matches!(read_pcd(&written_path), Err(Error::Truncated { .. })));
+
+        // So are huge counts in ascii data, and sizes that overflow.
+        let header = "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\n";
+        std::fs::write(&test_path, format!("{}COUNT 1 1 1000000000000\nPOINTS 1000\nDATA ascii\n1 2 3\n", header)).unwrap();
+        assert!(matches!(read_pcd(&test_path), Err(Error::Truncated { .. })));
+        std::fs::write(&test_path, format!("{}WIDTH {}\nHEIGHT 2\nDATA ascii\n", header, usize::MAX)).unwrap();
+        assert!(matches!(read_pcd(&test_path), Err(Error::Malformed(_))));
+    }
+}
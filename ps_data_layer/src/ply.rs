@@ -0,0 +1,622 @@
+// PLY import and export, ASCII and binary little endian.
+// Vertex positions are mapped to the cloud points, the usual vertex properties to
+// attribute channels:
+//   red, green, blue   -> Color "color" (float colors are scaled from 0..1)
+//   nx, ny, nz         -> Normal "normal"
+//   float / double     -> ScalarF32 / ScalarF64 with the property name
+//   unsigned integers  -> Label with the property name
+//   signed integers    -> ScalarF64 with the property name
+// On export other Color and Normal channels are written as <name>_red.. (uchar) and
+// <name>_x.. (float), and read back as such.
+// Faces are read from and written to the "face" element as vertex index lists.
+
+use crate::{Attribute, AttributeData, PointCloud};
+use kiss3d::nalgebra::Point3;
+
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+use std::io::Seek;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::io::BufWriter;
+use crate::error::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+// Content of a PLY file: the vertices with their properties, and the faces if any.
+#[derive(Debug)]
+pub struct PlyData {
+    pub cloud: PointCloud,
+    pub faces: Vec<Vec<u32>>,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyType {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Result<PlyType> {
+        match name {
+            "char" | "int8" => Ok(PlyType::Char),
+            "uchar" | "uint8" => Ok(PlyType::UChar),
+            "short" | "int16" => Ok(PlyType::Short),
+            "ushort" | "uint16" => Ok(PlyType::UShort),
+            "int" | "int32" => Ok(PlyType::Int),
+            "uint" | "uint32" => Ok(PlyType::UInt),
+            "float" | "float32" => Ok(PlyType::Float),
+            "double" | "float64" => Ok(PlyType::Double),
+            _ => Err(parse_error(format!("unknown property type {}", name))),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            PlyType::Char | PlyType::UChar => 1,
+            PlyType::Short | PlyType::UShort => 2,
+            PlyType::Int | PlyType::UInt | PlyType::Float => 4,
+            PlyType::Double => 8,
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        matches!(self, PlyType::Float | PlyType::Double)
+    }
+
+    fn is_signed_integer(&self) -> bool {
+        matches!(self, PlyType::Char | PlyType::Short | PlyType::Int)
+    }
+
+    // Every PLY type fits exactly in a f64.
+    fn decode(&self, bytes: &[u8]) -> f64 {
+        match self {
+            PlyType::Char => bytes[0] as i8 as f64,
+            PlyType::UChar => bytes[0] as f64,
+            PlyType::Short => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::UShort => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            PlyType::Int => i32::from_le_bytes(bytes[0..4].try_into().unwrap()) as f64,
+            PlyType::UInt => u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as f64,
+            PlyType::Float => f32::from_le_bytes(bytes[0..4].try_into().unwrap()) as f64,
+            PlyType::Double => f64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar { name: String, data_type: PlyType },
+    List { name: String, count_type: PlyType, item_type: PlyType },
+}
+
+impl PlyProperty {
+    fn name(&self) -> &str {
+        match self {
+            PlyProperty::Scalar { name, .. } | PlyProperty::List { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+
+// Reading
+
+pub fn read_ply(file_path: &Path) -> Result<PlyData> {
+    let file = File::open(file_path)?;
+    let mut reader = BufReader::new(file);
+    let (format, elements) = read_header(&mut reader)?;
+
+    let mut body: Box<dyn ValueSource> = match format {
+        PlyFormat::Ascii => {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            Box::new(AsciiValues { text, position: 0 })
+        }
+        PlyFormat::BinaryLittleEndian => {
+            let remaining = reader.get_ref().metadata()?.len().saturating_sub(reader.stream_position()?);
+            Box::new(BinaryValues { reader, remaining })
+        }
+    };
+
+    let mut cloud = PointCloud::new(Vec::new());
+    let mut faces = Vec::new();
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => cloud = read_vertices(body.as_mut(), element)?,
+            "face" => faces = read_faces(body.as_mut(), element)?,
+            _ => skip_element(body.as_mut(), element)?,
+        }
+    }
+
+    if let Some(face) = faces.iter().find(|face| face.iter().any(|&index| index as usize >= cloud.points.len())) {
+        return Err(parse_error(format!("face {:?} references a missing vertex", face)));
+    }
+
+    Ok(PlyData { cloud, faces })
+}
+
+
+fn read_header<R: BufRead>(reader: &mut R) -> Result<(PlyFormat, Vec<PlyElement>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != "ply" {
+        return Err(parse_error("missing ply magic line".to_string()));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(parse_error("missing end_header".to_string()));
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["end_header"] => break,
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::BinaryLittleEndian),
+            ["format", other, _] => return Err(Error::Unsupported(format!("unsupported ply format {}", other))),
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| parse_error(format!("bad element count {}", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_type, item_type, name] => add_property(&mut elements, PlyProperty::List {
+                name: name.to_string(),
+                count_type: PlyType::parse(count_type)?,
+                item_type: PlyType::parse(item_type)?,
+            })?,
+            ["property", data_type, name] => add_property(&mut elements, PlyProperty::Scalar {
+                name: name.to_string(),
+                data_type: PlyType::parse(data_type)?,
+            })?,
+            _ => return Err(parse_error(format!("unexpected header line {}", line.trim_end()))),
+        }
+    }
+
+    let format = format.ok_or_else(|| parse_error("missing format line".to_string()))?;
+    Ok((format, elements))
+}
+
+
+// Property names are unique within an element, the body columns are looked up by name.
+fn add_property(elements: &mut [PlyElement], property: PlyProperty) -> Result<()> {
+    let element = elements.last_mut().ok_or_else(|| parse_error("property declared before any element".to_string()))?;
+    if element.properties.iter().any(|other| other.name() == property.name()) {
+        return Err(parse_error(format!("duplicate property {} in element {}", property.name(), element.name)));
+    }
+    element.properties.push(property);
+    Ok(())
+}
+
+
+fn read_vertices(body: &mut dyn ValueSource, element: &PlyElement) -> Result<PointCloud> {
+    // Reading every scalar property into its own column.
+    let mut columns: Vec<(String, PlyType, Vec<f64>)> = Vec::new();
+    for property in &element.properties {
+        if let PlyProperty::Scalar { name, data_type } = property {
+            columns.push((name.clone(), *data_type, Vec::with_capacity(element.count.min(body.remaining()))));
+        }
+    }
+
+    for _ in 0..element.count {
+        let mut column = 0;
+        for property in &element.properties {
+            match property {
+                PlyProperty::Scalar { data_type, .. } => {
+                    columns[column].2.push(body.next_value(*data_type)?);
+                    column += 1;
+                }
+                PlyProperty::List { count_type, item_type, .. } => skip_list(body, *count_type, *item_type)?,
+            }
+        }
+    }
+
+    let take_column = |columns: &mut Vec<(String, PlyType, Vec<f64>)>, name: &str| {
+        columns.iter().position(|(column_name, _, _)| column_name == name).map(|index| columns.remove(index))
+    };
+    // A partial group stays in the columns and is read as separate scalars.
+    let take_columns = |columns: &mut Vec<(String, PlyType, Vec<f64>)>, names: [&str; 3]| {
+        if names.iter().all(|name| columns.iter().any(|(column_name, _, _)| column_name == name)) {
+            names.map(|name| take_column(columns, name))
+        } else {
+            [None, None, None]
+        }
+    };
+
+    // Positions
+    let (Some(x), Some(y), Some(z)) = (take_column(&mut columns, "x"), take_column(&mut columns, "y"), take_column(&mut columns, "z")) else {
+        return Err(parse_error("vertex element without x, y, z properties".to_string()));
+    };
+    let points = (0..element.count)
+        .map(|index| Point3::new(x.2[index] as f32, y.2[index] as f32, z.2[index] as f32))
+        .collect();
+    let mut cloud = PointCloud::new(points);
+
+    // Colors
+    if let [Some(red), Some(green), Some(blue)] = take_columns(&mut columns, ["red", "green", "blue"]) {
+        let channels = [red, green, blue];
+        let to_byte = |(_, data_type, values): &(String, PlyType, Vec<f64>), index: usize| {
+            let value = if data_type.is_float() { values[index] * 255. } else { values[index] };
+            value.round().clamp(0., 255.) as u8
+        };
+        let colors = (0..element.count)
+            .map(|index| [to_byte(&channels[0], index), to_byte(&channels[1], index), to_byte(&channels[2], index)])
+            .collect();
+        cloud.attributes.push(Attribute::new("color", AttributeData::Color(colors)));
+    }
+
+    // Normals
+    if let [Some(nx), Some(ny), Some(nz)] = take_columns(&mut columns, ["nx", "ny", "nz"]) {
+        let normals = (0..element.count)
+            .map(|index| [nx.2[index] as f32, ny.2[index] as f32, nz.2[index] as f32])
+            .collect();
+        cloud.attributes.push(Attribute::new("normal", AttributeData::Normal(normals)));
+    }
+
+    // Everything else
+    while !columns.is_empty() {
+        let column = columns.remove(0);
+        if let Some(attribute) = take_group(&mut columns, &column) {
+            cloud.attributes.push(attribute);
+            continue;
+        }
+        let (name, data_type, values) = column;
+        let data = match data_type {
+            PlyType::Float => AttributeData::ScalarF32(values.into_iter().map(|value| value as f32).collect()),
+            PlyType::Double => AttributeData::ScalarF64(values),
+            _ if data_type.is_signed_integer() => AttributeData::ScalarF64(values),
+            _ => AttributeData::Label(values.into_iter().map(|value| value as u32).collect()),
+        };
+        cloud.attributes.push(Attribute::new(&name, data));
+    }
+
+    Ok(cloud)
+}
+
+
+// Color and Normal channels written as <name>_red.. or <name>_x.., starting at the given column.
+fn take_group(columns: &mut Vec<(String, PlyType, Vec<f64>)>, first: &(String, PlyType, Vec<f64>)) -> Option<Attribute> {
+    for (suffixes, group_type) in [(["_red", "_green", "_blue"], PlyType::UChar), (["_x", "_y", "_z"], PlyType::Float)] {
+        let Some(prefix) = first.0.strip_suffix(suffixes[0]).filter(|prefix| !prefix.is_empty() && first.1 == group_type) else { continue };
+        let position = |suffix: &str| columns.iter()
+            .position(|(name, data_type, _)| *data_type == group_type && name.strip_prefix(prefix) == Some(suffix));
+        let (Some(second), Some(third)) = (position(suffixes[1]), position(suffixes[2])) else { continue };
+
+        // Removing the later column first keeps the other position valid.
+        let (second, third) = match second < third {
+            true => { let third = columns.remove(third); (columns.remove(second), third) }
+            false => { let second = columns.remove(second); (second, columns.remove(third)) }
+        };
+        let components = (0..first.2.len()).map(|index| [first.2[index], second.2[index], third.2[index]]);
+        let data = match group_type {
+            PlyType::UChar => AttributeData::Color(components.map(|color| color.map(|value| value as u8)).collect()),
+            _ => AttributeData::Normal(components.map(|normal| normal.map(|value| value as f32)).collect()),
+        };
+        return Some(Attribute::new(prefix, data));
+    }
+    None
+}
+
+
+fn read_faces(body: &mut dyn ValueSource, element: &PlyElement) -> Result<Vec<Vec<u32>>> {
+    let mut faces = Vec::with_capacity(element.count.min(body.remaining()));
+    for _ in 0..element.count {
+        let mut face = None;
+        for property in &element.properties {
+            match property {
+                PlyProperty::List { name, count_type, item_type } if face.is_none() && (name == "vertex_indices" || name == "vertex_index") => {
+                    let count = body.next_value(*count_type)? as usize;
+                    let mut indices = Vec::with_capacity(count.min(body.remaining()));
+                    for _ in 0..count {
+                        let index = body.next_value(*item_type)?;
+                        if index < 0. {
+                            return Err(parse_error(format!("negative vertex index {}", index)));
+                        }
+                        indices.push(index as u32);
+                    }
+                    face = Some(indices);
+                }
+                PlyProperty::List { count_type, item_type, .. } => skip_list(body, *count_type, *item_type)?,
+                PlyProperty::Scalar { data_type, .. } => { body.next_value(*data_type)?; }
+            }
+        }
+        faces.push(face.ok_or_else(|| parse_error("face element without vertex_indices".to_string()))?);
+    }
+    Ok(faces)
+}
+
+
+fn skip_element(body: &mut dyn ValueSource, element: &PlyElement) -> Result<()> {
+    for _ in 0..element.count {
+        for property in &element.properties {
+            match property {
+                PlyProperty::Scalar { data_type, .. } => { body.next_value(*data_type)?; }
+                PlyProperty::List { count_type, item_type, .. } => skip_list(body, *count_type, *item_type)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+
+fn skip_list(body: &mut dyn ValueSource, count_type: PlyType, item_type: PlyType) -> Result<()> {
+    let count = body.next_value(count_type)? as usize;
+    for _ in 0..count {
+        body.next_value(item_type)?;
+    }
+    Ok(())
+}
+
+
+// The body of a PLY file, seen as a flat sequence of values.
+trait ValueSource {
+    fn next_value(&mut self, data_type: PlyType) -> Result<f64>;
+
+    // Upper bound on the number of values left, to size buffers from the file counts.
+    fn remaining(&self) -> usize;
+}
+
+struct AsciiValues {
+    text: String,
+    // Byte offset of the next token.
+    position: usize,
+}
+
+impl ValueSource for AsciiValues {
+    fn next_value(&mut self, _data_type: PlyType) -> Result<f64> {
+        let rest = self.text[self.position..].trim_start();
+        if rest.is_empty() {
+            return Err(parse_error("ply body ended early".to_string()));
+        }
+        let start = self.text.len() - rest.len();
+        self.position = start + rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let token = &self.text[start..self.position];
+        token.parse().map_err(|_| parse_error(format!("bad value {}", token)))
+    }
+
+    fn remaining(&self) -> usize {
+        // Every value but the last takes a separator.
+        (self.text.len() - self.position).div_ceil(2)
+    }
+}
+
+struct BinaryValues<R: Read> {
+    reader: R,
+    // Bytes left in the file
+    remaining: u64,
+}
+
+impl<R: Read> ValueSource for BinaryValues<R> {
+    fn next_value(&mut self, data_type: PlyType) -> Result<f64> {
+        let mut buffer = [0u8; 8];
+        self.reader.read_exact(&mut buffer[..data_type.size()])?;
+        self.remaining = self.remaining.saturating_sub(data_type.size() as u64);
+        Ok(data_type.decode(&buffer))
+    }
+
+    fn remaining(&self) -> usize {
+        usize::try_from(self.remaining).unwrap_or(usize::MAX)
+    }
+}
+
+
+// Writing
+
+pub fn write_ply(file_path: &Path, cloud: &PointCloud, faces: &[Vec<u32>], format: PlyFormat) -> Result<usize> {
+    if let Some(attribute) = cloud.attributes.iter().find(|attribute| attribute.data.len() != cloud.points.len()) {
+        return Err(Error::InvalidInput(format!(
+            "attribute {} has {} values for {} points", attribute.name, attribute.data.len(), cloud.points.len())));
+    }
+    if let Some(face) = faces.iter().find(|face| face.len() > u8::MAX as usize) {
+        return Err(Error::InvalidInput(format!("face with {} vertices", face.len())));
+    }
+
+    let file = File::create(file_path)?;
+    let mut writer = BufWriter::new(file);
+
+    // Header
+    writeln!(writer, "ply")?;
+    match format {
+        PlyFormat::Ascii => writeln!(writer, "format ascii 1.0")?,
+        PlyFormat::BinaryLittleEndian => writeln!(writer, "format binary_little_endian 1.0")?,
+    }
+    writeln!(writer, "comment written by ps_data_layer")?;
+    writeln!(writer, "element vertex {}", cloud.points.len())?;
+    writeln!(writer, "property float x")?;
+    writeln!(writer, "property float y")?;
+    writeln!(writer, "property float z")?;
+    for attribute in &cloud.attributes {
+        for (type_name, property_name) in property_declarations(attribute) {
+            writeln!(writer, "property {} {}", type_name, property_name)?;
+        }
+    }
+    if !faces.is_empty() {
+        writeln!(writer, "element face {}", faces.len())?;
+        writeln!(writer, "property list uchar int vertex_indices")?;
+    }
+    writeln!(writer, "end_header")?;
+
+    // Vertices
+    for (index, point) in cloud.points.iter().enumerate() {
+        match format {
+            PlyFormat::Ascii => {
+                write!(writer, "{} {} {}", point.x, point.y, point.z)?;
+                for attribute in &cloud.attributes {
+                    write_ascii_values(&mut writer, &attribute.data, index)?;
+                }
+                writeln!(writer)?;
+            }
+            PlyFormat::BinaryLittleEndian => {
+                writer.write_all(&point.x.to_le_bytes())?;
+                writer.write_all(&point.y.to_le_bytes())?;
+                writer.write_all(&point.z.to_le_bytes())?;
+                for attribute in &cloud.attributes {
+                    write_binary_values(&mut writer, &attribute.data, index)?;
+                }
+            }
+        }
+    }
+
+    // Faces
+    for face in faces {
+        match format {
+            PlyFormat::Ascii => {
+                write!(writer, "{}", face.len())?;
+                for index in face {
+                    write!(writer, " {}", index)?;
+                }
+                writeln!(writer)?;
+            }
+            PlyFormat::BinaryLittleEndian => {
+                writer.write_all(&[face.len() as u8])?;
+                for &index in face {
+                    writer.write_all(&(index as i32).to_le_bytes())?;
+                }
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(cloud.points.len())
+}
+
+
+fn property_declarations(attribute: &Attribute) -> Vec<(&'static str, String)> {
+    let name = &attribute.name;
+    match &attribute.data {
+        AttributeData::Color(_) if name == "color" => vec![
+            ("uchar", "red".to_string()), ("uchar", "green".to_string()), ("uchar", "blue".to_string())],
+        AttributeData::Color(_) => vec![
+            ("uchar", format!("{}_red", name)), ("uchar", format!("{}_green", name)), ("uchar", format!("{}_blue", name))],
+        AttributeData::Normal(_) if name == "normal" => vec![
+            ("float", "nx".to_string()), ("float", "ny".to_string()), ("float", "nz".to_string())],
+        AttributeData::Normal(_) => vec![
+            ("float", format!("{}_x", name)), ("float", format!("{}_y", name)), ("float", format!("{}_z", name))],
+        AttributeData::ScalarF32(_) => vec![("float", name.clone())],
+        AttributeData::ScalarF64(_) => vec![("double", name.clone())],
+        AttributeData::Label(_) => vec![("uint", name.clone())],
+    }
+}
+
+
+fn write_ascii_values<W: Write>(writer: &mut W, data: &AttributeData, index: usize) -> std::io::Result<()> {
+    match data {
+        AttributeData::Color(values) => write!(writer, " {} {} {}", values[index][0], values[index][1], values[index][2]),
+        AttributeData::Normal(values) => write!(writer, " {} {} {}", values[index][0], values[index][1], values[index][2]),
+        AttributeData::ScalarF32(values) => write!(writer, " {}", values[index]),
+        AttributeData::ScalarF64(values) => write!(writer, " {}", values[index]),
+        AttributeData::Label(values) => write!(writer, " {}", values[index]),
+    }
+}
+
+
+fn write_binary_values<W: Write>(writer: &mut W, data: &AttributeData, index: usize) -> std::io::Result<()> {
+    match data {
+        AttributeData::Color(values) => writer.write_all(&values[index]),
+        AttributeData::Normal(values) => {
+            for component in values[index] {
+                writer.write_all(&component.to_le_bytes())?;
+            }
+            Ok(())
+        }
+        AttributeData::ScalarF32(values) => writer.write_all(&values[index].to_le_bytes()),
+        AttributeData::ScalarF64(values) => writer.write_all(&values[index].to_le_bytes()),
+        AttributeData::Label(values) => writer.write_all(&values[index].to_le_bytes()),
+    }
+}
+
+
+fn parse_error(message: String) -> Error {
+    Error::Malformed(message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cloud() -> PointCloud {
+        let mut cloud = PointCloud::new(vec![
+            Point3::new(0., 0., 0.),
+            Point3::new(1., 0., 0.),
+            Point3::new(0., 1., 0.5)]);
+        cloud.set_attribute("color", AttributeData::Color(vec![[255, 0, 0], [0, 255, 0], [0, 0, 255]])).unwrap();
+        cloud.set_attribute("normal", AttributeData::Normal(vec![[0., 0., 1.]; 3])).unwrap();
+        cloud.set_attribute("intensity", AttributeData::ScalarF32(vec![0.1, 0.2, 0.3])).unwrap();
+        cloud.set_attribute("energy", AttributeData::ScalarF64(vec![1e-9, 2., 3.])).unwrap();
+        cloud.set_attribute("label", AttributeData::Label(vec![1, 2, 3])).unwrap();
+        cloud.set_attribute("highlight", AttributeData::Color(vec![[1, 2, 3], [4, 5, 6], [7, 8, 9]])).unwrap();
+        cloud
+    }
+
+    #[test]
+    fn ply_round_trip_test() {
+        let cloud = test_cloud();
+        let faces = vec![vec![0, 1, 2]];
+
+        for (format, file_name) in [(PlyFormat::Ascii, "ps_data_layer_ascii.ply"), (PlyFormat::BinaryLittleEndian, "ps_data_layer_binary.ply")] {
+            let test_path = std::env::temp_dir().join(file_name);
+            assert_eq!(3, write_ply(&test_path, &cloud, &faces, format).expect("Writing failed"));
+
+            let data = read_ply(&test_path).expect("Reading failed");
+            assert_eq!(cloud.points, data.cloud.points, "Positions mismatch for {:?}", format);
+            assert_eq!(cloud.attributes, data.cloud.attributes, "Attributes mismatch for {:?}", format);
+            assert_eq!(faces, data.faces, "Faces mismatch for {:?}", format);
+        }
+    }
+
+    #[test]
+    fn ply_foreign_file_test() {
+        // Float colors, a signed property, an unknown element and quads.
+        let text = "ply\nformat ascii 1.0\ncomment from elsewhere\n\
+            element vertex 4\nproperty double x\nproperty double y\nproperty double z\n\
+            property float red\nproperty float green\nproperty float blue\nproperty short offset\n\
+            element material 1\nproperty uchar shininess\n\
+            element face 1\nproperty list uchar uint vertex_index\nend_header\n\
+            0 0 0 1 0 0 -3\n1 0 0 0 1 0 -2\n1 1 0 0 0 1 -1\n0 1 0 1 1 1 0\n\
+            12\n\
+            4 0 1 2 3\n";
+        let test_path = std::env::temp_dir().join("ps_data_layer_foreign.ply");
+        std::fs::write(&test_path, text).unwrap();
+
+        let data = read_ply(&test_path).expect("Reading failed");
+        assert_eq!(4, data.cloud.points.len());
+        assert_eq!(Some(&AttributeData::Color(vec![[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]])), data.cloud.get_attribute("color"));
+        assert_eq!(Some(&AttributeData::ScalarF64(vec![-3., -2., -1., 0.])), data.cloud.get_attribute("offset"));
+        assert_eq!(vec![vec![0, 1, 2, 3]], data.faces);
+
+        // Element counts larger than the body do not reserve memory for them.
+        std::fs::write(&test_path, "ply\nformat binary_little_endian 1.0\nelement vertex 4000000000\n\
+            property float x\nproperty float y\nproperty float z\nend_header\n\0\0\0\0").unwrap();
+        assert!(read_ply(&test_path).is_err());
+
+        // Duplicate property names are rejected in the header.
+        std::fs::write(&test_path, "ply\nformat ascii 1.0\nelement vertex 1\n\
+            property float x\nproperty float y\nproperty float z\n\
+            property uchar red\nproperty uchar red\nproperty uchar green\nend_header\n0 0 0 1 2 3\n").unwrap();
+        assert!(matches!(read_ply(&test_path), Err(Error::Malformed(_))));
+    }
+}
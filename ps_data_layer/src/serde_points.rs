@@ -0,0 +1,16 @@
+// Positions as [x, y, z] arrays, as kiss3d's nalgebra is built without its serde feature.
+
+use crate::Point3;
+
+use serde::{Deserialize, Deserializer, Serializer};
+
+
+pub fn serialize<S: Serializer>(points: &[Point3<f32>], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(points.iter().map(|point| [point.x, point.y, point.z]))
+}
+
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Point3<f32>>, D::Error> {
+    let points = Vec::<[f32; 3]>::deserialize(deserializer)?;
+    Ok(points.into_iter().map(|[x, y, z]| Point3::new(x, y, z)).collect())
+}
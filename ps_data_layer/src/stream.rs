@@ -0,0 +1,291 @@
+// Single-pass access to large points files.
+// MappedPoints maps the file in memory and decodes positions on demand; for f32
+// files on little endian machines the positions can be borrowed without any copy.
+// PointChunks reads the file sequentially in fixed-size chunks, for sources that
+// cannot be mapped or when a bounded memory use is wanted.
+// Both only cover the positions: attribute channels follow them in the file and
+// are read by PointCloud. Compressed files (see compression.rs) are not supported.
+
+use crate::header::{self, Encoding, PointsHeader, ScalarType};
+use crate::error::{Error, Result};
+use crate::Point3;
+
+use std::fs::File;
+use std::path::Path;
+use std::io::BufRead;
+use std::io::BufReader;
+
+use memmap2::Mmap;
+
+
+// Where the positions are in a points file.
+#[derive(Debug, Clone)]
+struct PointsLayout {
+    header: Option<PointsHeader>,
+    offset: usize,
+    count: usize,
+    scalar_type: ScalarType,
+    // Offset of the first byte after the positions, checked against the file size
+    end: usize,
+}
+
+impl PointsLayout {
+    // Bytes taken by a single point.
+    fn stride(&self) -> usize {
+        3 * self.scalar_type.size()
+    }
+
+    fn end(&self) -> usize {
+        self.end
+    }
+}
+
+
+// Reads the header, if any, at the start of the reader and checks the file holds all the points.
+fn read_layout<R: BufRead>(reader: &mut R, file_size: u64) -> Result<PointsLayout> {
+    // Files without the magic bytes are legacy headerless files.
+    if !header::has_magic(reader.fill_buf()?) {
+        if !file_size.is_multiple_of(12) {
+            return Err(Error::Truncated { expected: file_size.next_multiple_of(12), found: file_size });
+        }
+        let count = usize::try_from(file_size / 12)
+            .map_err(|_| Error::Unsupported(format!("{} bytes file too large for this platform", file_size)))?;
+        return Ok(PointsLayout { header: None, offset: 0, count, scalar_type: ScalarType::F32, end: count * 12 });
+    }
+
+    let header = PointsHeader::read_from(reader)?;
+    if header.encoding != Encoding::Raw {
+        return Err(Error::Unsupported("compressed points files must be read whole, with PointCloud".to_string()));
+    }
+    let expected_size = header.points_size()?.checked_add(header.header_size as u64)
+        .ok_or_else(|| Error::BadHeader(format!("point count {} is too large", header.point_count)))?;
+    if file_size < expected_size {
+        return Err(Error::Truncated { expected: expected_size, found: file_size });
+    }
+
+    // Within the file size, so within the address space once mapped.
+    let too_large = || Error::Unsupported(format!("{} bytes of points too large for this platform", expected_size));
+    Ok(PointsLayout {
+        offset: header.header_size as usize,
+        count: usize::try_from(header.point_count).map_err(|_| too_large())?,
+        scalar_type: header.scalar_type,
+        end: usize::try_from(expected_size).map_err(|_| too_large())?,
+        header: Some(header),
+    })
+}
+
+
+fn decode_point(bytes: &[u8], scalar_type: ScalarType) -> Point3<f32> {
+    match scalar_type {
+        ScalarType::F32 => {
+            let value = |index: usize| f32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap());
+            Point3::new(value(0), value(1), value(2))
+        }
+        ScalarType::F64 => {
+            let value = |index: usize| f64::from_le_bytes(bytes[index * 8..index * 8 + 8].try_into().unwrap()) as f32;
+            Point3::new(value(0), value(1), value(2))
+        }
+    }
+}
+
+
+// Mapped
+
+pub struct MappedPoints {
+    map: Mmap,
+    layout: PointsLayout,
+}
+
+impl MappedPoints {
+    // The file stays valid while mapped even if a writer replaces it, as writers rename
+    // a new file over it rather than modifying it in place. On Windows that rename fails
+    // with an access error until the map is dropped.
+    pub fn open(i_path: &Path) -> Result<MappedPoints> {
+        let file = File::open(i_path)?;
+        let file_size = file.metadata()?.len();
+
+        // SAFETY: this assumes files are only ever replaced by an atomic rename (see atomic.rs),
+        // never truncated or written in place, by this process or any other. Nothing enforces
+        // it for other programs: one truncating the file while mapped makes reads fault.
+        let map = unsafe { Mmap::map(&file)? };
+        let layout = read_layout(&mut &map[..], file_size)?;
+        Ok(MappedPoints { map, layout })
+    }
+
+    pub fn header(&self) -> Option<&PointsHeader> {
+        self.layout.header.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.layout.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layout.count == 0
+    }
+
+    pub fn get(&self, i_index: usize) -> Option<Point3<f32>> {
+        if i_index >= self.layout.count {
+            return None;
+        }
+        let start = self.layout.offset + i_index * self.layout.stride();
+        Some(decode_point(&self.map[start..start + self.layout.stride()], self.layout.scalar_type))
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = Point3<f32>> + '_ {
+        self.points_bytes()
+            .chunks_exact(self.layout.stride())
+            .map(|bytes| decode_point(bytes, self.layout.scalar_type))
+    }
+
+    // Zero-copy view of the positions. None when they need decoding: f64 files,
+    // big endian machines, or a header size breaking the alignment.
+    pub fn as_slice(&self) -> Option<&[[f32; 3]]> {
+        if self.layout.scalar_type != ScalarType::F32 || cfg!(target_endian = "big") {
+            return None;
+        }
+        bytemuck::try_cast_slice(self.points_bytes()).ok()
+    }
+
+    // Raw bytes following the positions, where the attribute channels are stored.
+    pub fn trailing_bytes(&self) -> &[u8] {
+        &self.map[self.layout.end()..]
+    }
+
+    fn points_bytes(&self) -> &[u8] {
+        &self.map[self.layout.offset..self.layout.end()]
+    }
+}
+
+
+// Streaming
+
+pub struct PointChunks<R: BufRead> {
+    reader: R,
+    layout: PointsLayout,
+    chunk_size: usize,
+    remaining: usize,
+    buffer: Vec<u8>,
+}
+
+impl PointChunks<BufReader<File>> {
+    pub fn open(i_path: &Path, i_chunk_size: usize) -> Result<PointChunks<BufReader<File>>> {
+        let file = File::open(i_path)?;
+        let file_size = file.metadata()?.len();
+        PointChunks::new(BufReader::new(file), file_size, i_chunk_size)
+    }
+}
+
+impl<R: BufRead> PointChunks<R> {
+    // The size of the source is needed to tell truncated files from complete ones upfront.
+    pub fn new(mut i_reader: R, i_size: u64, i_chunk_size: usize) -> Result<PointChunks<R>> {
+        if i_chunk_size == 0 {
+            return Err(Error::InvalidInput("chunk size must be positive".to_string()));
+        }
+        let layout = read_layout(&mut i_reader, i_size)?;
+        Ok(PointChunks {
+            reader: i_reader,
+            remaining: layout.count,
+            layout,
+            chunk_size: i_chunk_size,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn header(&self) -> Option<&PointsHeader> {
+        self.layout.header.as_ref()
+    }
+
+    // Total points in the file, read or not.
+    pub fn len(&self) -> usize {
+        self.layout.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layout.count == 0
+    }
+
+    fn read_chunk(&mut self) -> Result<Vec<Point3<f32>>> {
+        let count = self.remaining.min(self.chunk_size);
+        let stride = self.layout.stride();
+
+        // A whole chunk per read, instead of one point at a time.
+        self.buffer.resize(count * stride, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        self.remaining -= count;
+
+        Ok(self.buffer
+            .chunks_exact(stride)
+            .map(|bytes| decode_point(bytes, self.layout.scalar_type))
+            .collect())
+    }
+}
+
+impl<R: BufRead> Iterator for PointChunks<R> {
+    type Item = Result<Vec<Point3<f32>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let chunk = self.read_chunk();
+        if chunk.is_err() {
+            // Stopping after the first failure.
+            self.remaining = 0;
+        }
+        Some(chunk)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PointCloud;
+
+    #[test]
+    fn mapped_and_chunks_test() {
+        let test_path = std::env::temp_dir().join("ps_data_layer_stream.bin");
+        let points: Vec<Point3<f32>> = (0..1000).map(|index| Point3::new(index as f32, 0.5, -(index as f32))).collect();
+        PointCloud::new(points.clone()).write_to_file(&test_path).expect("Writing failed");
+
+        let mapped = MappedPoints::open(&test_path).expect("Mapping failed");
+        assert_eq!(points.len(), mapped.len());
+        assert_eq!(Some(points[999]), mapped.get(999));
+        assert_eq!(None, mapped.get(1000));
+        assert_eq!(points, mapped.iter().collect::<Vec<_>>());
+        if cfg!(target_endian = "little") {
+            let slice = mapped.as_slice().expect("Expected a zero-copy view");
+            assert_eq!([3., 0.5, -3.], slice[3]);
+        }
+
+        let chunks: Vec<Vec<Point3<f32>>> = PointChunks::open(&test_path, 300).unwrap()
+            .collect::<Result<_>>()
+            .expect("Streaming failed");
+        assert_eq!(vec![300, 300, 300, 100], chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>());
+        assert_eq!(points, chunks.concat());
+
+        // Legacy files, and a reader that cannot seek.
+        let legacy: Vec<u8> = points.iter().flat_map(|point| [point.x, point.y, point.z]).flat_map(f32::to_le_bytes).collect();
+        let chunks: Vec<Vec<Point3<f32>>> = PointChunks::new(&legacy[..], legacy.len() as u64, 512).unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(points, chunks.concat());
+
+        // Truncated files are rejected upfront.
+        let bytes = std::fs::read(&test_path).unwrap();
+        assert!(matches!(PointChunks::new(&bytes[..bytes.len() - 4], bytes.len() as u64 - 4, 10), Err(Error::Truncated { .. })));
+
+        // As are point counts overflowing the size computation.
+        let mut corrupt = bytes.clone();
+        corrupt[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(PointChunks::new(&corrupt[..], corrupt.len() as u64, 10), Err(Error::BadHeader(_))));
+
+        // Replacing a mapped file keeps the old content in the map. Windows refuses the rename.
+        if cfg!(unix) {
+            PointCloud::new(vec![Point3::new(1., 2., 3.)]).write_to_file(&test_path).expect("Replacing failed");
+            assert_eq!(points, mapped.iter().collect::<Vec<_>>());
+            assert_eq!(1, MappedPoints::open(&test_path).unwrap().len());
+        }
+    }
+}
@@ -0,0 +1,394 @@
+// Timeline files: an append-only sequence of point cloud frames, to replay simulations.
+// Layout (little endian):
+//   magic            [u8; 4]  "PSTL"
+//   version          u16
+//   header size      u16      bytes from the start of the file to the first frame
+//   then the frames, one after the other:
+//     kind           u8       0 keyframe, 1 delta against the previous frame
+//     payload size   u64      bytes following, to skip frames without decoding them
+//     point count    u64
+//     keyframe:      3 x f32 per point
+//     delta:         changed count u64, then u64 index + 3 x f32 per changed point
+//     attributes     u8 flag, 0 when identical to the previous frame, 1 followed by
+//                    the attribute section of the points file (see attributes.rs)
+// Deltas need the same point count as the previous frame; frames with a different
+// count, or too many changes, are stored as keyframes. A keyframe is also forced every
+// keyframe interval frames, bounding the frames to decode to reach any frame.
+// Frames are written in one go, so a crash leaves at most a truncated last frame,
+// ignored by the reader and dropped when appending again.
+
+use crate::attributes;
+use crate::error::{Error, Result};
+use crate::{Point3, PointCloud};
+
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::BufReader;
+
+pub const MAGIC: [u8; 4] = *b"PSTL";
+pub const FORMAT_VERSION: u16 = 1;
+pub const HEADER_SIZE: u16 = 8;
+
+const FRAME_HEADER_SIZE: u64 = 9;
+const KEYFRAME: u8 = 0;
+const DELTA: u8 = 1;
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct TimelineOptions {
+    // Keyframe forced every this many frames, 1 to store every frame whole.
+    pub keyframe_interval: usize,
+}
+
+impl Default for TimelineOptions {
+    fn default() -> Self {
+        TimelineOptions { keyframe_interval: 50 }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameEntry {
+    pub offset: u64,
+    pub keyframe: bool,
+    pub payload_size: u64,
+}
+
+
+// Writing
+
+pub struct TimelineWriter {
+    file: File,
+    options: TimelineOptions,
+    frames: usize,
+    since_keyframe: usize,
+    previous: Option<PointCloud>,
+}
+
+impl TimelineWriter {
+    // Starts a new timeline, replacing any existing file.
+    pub fn create(i_path: &Path, i_options: TimelineOptions) -> Result<TimelineWriter> {
+        if let Some(parent) = i_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = File::create(i_path)?;
+        file.write_all(&MAGIC)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        file.write_all(&HEADER_SIZE.to_le_bytes())?;
+        file.flush()?;
+
+        Ok(TimelineWriter { file, options: i_options, frames: 0, since_keyframe: 0, previous: None })
+    }
+
+    // Continues an existing timeline, or starts a new one if there is none.
+    pub fn append(i_path: &Path, i_options: TimelineOptions) -> Result<TimelineWriter> {
+        if !i_path.exists() {
+            return TimelineWriter::create(i_path, i_options);
+        }
+
+        let mut reader = TimelineReader::open(i_path)?;
+        let frames = reader.len();
+        let previous = match frames {
+            0 => None,
+            _ => Some(reader.read_frame(frames - 1)?),
+        };
+        let since_keyframe = reader.frames.iter().rev().take_while(|frame| !frame.keyframe).count() + 1;
+
+        // Dropping a truncated last frame, if any.
+        let mut file = OpenOptions::new().write(true).open(i_path)?;
+        file.set_len(reader.end)?;
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(TimelineWriter { file, options: i_options, frames, since_keyframe, previous })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    // Appends a frame, returning its index.
+    pub fn push(&mut self, i_cloud: &PointCloud) -> Result<usize> {
+        if let Some(attribute) = i_cloud.attributes.iter().find(|attribute| attribute.data.len() != i_cloud.points.len()) {
+            return Err(Error::InvalidInput(format!(
+                "attribute {} has {} values for {} points", attribute.name, attribute.data.len(), i_cloud.points.len())));
+        }
+
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&(i_cloud.points.len() as u64).to_le_bytes());
+
+        let changed = match &self.previous {
+            Some(previous) if previous.points.len() == i_cloud.points.len()
+                && self.since_keyframe < self.options.keyframe_interval.max(1) => {
+                let changed: Vec<usize> = (0..i_cloud.points.len())
+                    .filter(|&index| !same_bits(&previous.points[index], &i_cloud.points[index]))
+                    .collect();
+                // A delta point takes 20 bytes instead of 12, only worth it for few changes.
+                Some(changed).filter(|changed| changed.len() * 20 < i_cloud.points.len() * 12)
+            }
+            _ => None,
+        };
+
+        let kind = match &changed {
+            Some(changed) => {
+                payload.extend_from_slice(&(changed.len() as u64).to_le_bytes());
+                for &index in changed {
+                    payload.extend_from_slice(&(index as u64).to_le_bytes());
+                    write_point(&mut payload, &i_cloud.points[index]);
+                }
+                self.since_keyframe += 1;
+                DELTA
+            }
+            None => {
+                for point in &i_cloud.points {
+                    write_point(&mut payload, point);
+                }
+                self.since_keyframe = 1;
+                KEYFRAME
+            }
+        };
+
+        let same_attributes = kind == DELTA
+            && self.previous.as_ref().is_some_and(|previous| previous.attributes == i_cloud.attributes);
+        if same_attributes {
+            payload.push(0);
+        } else {
+            payload.push(1);
+            attributes::write_attributes(&mut payload, &i_cloud.attributes)?;
+        }
+
+        // The whole frame in one write.
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE as usize + payload.len());
+        frame.push(kind);
+        frame.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        self.file.write_all(&frame)?;
+        self.file.flush()?;
+
+        let mut previous = PointCloud::new(i_cloud.points.clone());
+        previous.attributes = i_cloud.attributes.clone();
+        self.previous = Some(previous);
+        self.frames += 1;
+        Ok(self.frames - 1)
+    }
+}
+
+
+fn write_point(buffer: &mut Vec<u8>, point: &Point3<f32>) {
+    for value in [point.x, point.y, point.z] {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+
+fn same_bits(a: &Point3<f32>, b: &Point3<f32>) -> bool {
+    a.x.to_bits() == b.x.to_bits() && a.y.to_bits() == b.y.to_bits() && a.z.to_bits() == b.z.to_bits()
+}
+
+
+// Reading
+
+pub struct TimelineReader {
+    reader: BufReader<File>,
+    frames: Vec<FrameEntry>,
+    // End of the last complete frame.
+    end: u64,
+}
+
+impl TimelineReader {
+    // Indexes the frames, skipping over their payloads.
+    pub fn open(i_path: &Path) -> Result<TimelineReader> {
+        let file = File::open(i_path)?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut header = [0u8; HEADER_SIZE as usize];
+        reader.read_exact(&mut header)?;
+        if header[0..4] != MAGIC {
+            return Err(Error::BadHeader("not a timeline file".to_string()));
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let header_size = u16::from_le_bytes([header[6], header[7]]) as u64;
+        if header_size < HEADER_SIZE as u64 {
+            return Err(Error::BadHeader(format!("header size {} is too small", header_size)));
+        }
+
+        let mut frames = Vec::new();
+        let mut offset = header_size;
+        while offset + FRAME_HEADER_SIZE <= file_size {
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut frame_header = [0u8; FRAME_HEADER_SIZE as usize];
+            reader.read_exact(&mut frame_header)?;
+            let payload_size = u64::from_le_bytes(frame_header[1..9].try_into().unwrap());
+            let keyframe = match frame_header[0] {
+                KEYFRAME => true,
+                DELTA => false,
+                other => return Err(Error::Malformed(format!("unknown frame kind {} at byte {}", other, offset))),
+            };
+            if frame_header[0] == DELTA && frames.is_empty() {
+                return Err(Error::Malformed("timeline starting with a delta frame".to_string()));
+            }
+
+            let end = (offset + FRAME_HEADER_SIZE).saturating_add(payload_size);
+            if end > file_size {
+                println!("Warning: ignoring a truncated frame at the end of {}", i_path.display());
+                break;
+            }
+            frames.push(FrameEntry { offset, keyframe, payload_size });
+            offset = end;
+        }
+
+        Ok(TimelineReader { reader, frames, end: offset.min(file_size).max(header_size) })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn frames(&self) -> &[FrameEntry] {
+        &self.frames
+    }
+
+    // Decodes the closest keyframe at or before the frame, then the deltas up to it.
+    pub fn read_frame(&mut self, i_index: usize) -> Result<PointCloud> {
+        if i_index >= self.frames.len() {
+            return Err(Error::InvalidInput(format!("frame {} out of {} frames", i_index, self.frames.len())));
+        }
+
+        let keyframe = (0..=i_index).rev()
+            .find(|&index| self.frames[index].keyframe)
+            .ok_or_else(|| Error::Malformed("no keyframe before the frame".to_string()))?;
+
+        let mut cloud = PointCloud::new(Vec::new());
+        for index in keyframe..=i_index {
+            self.apply_frame(index, &mut cloud)?;
+        }
+        Ok(cloud)
+    }
+
+    fn apply_frame(&mut self, i_index: usize, cloud: &mut PointCloud) -> Result<()> {
+        let frame = self.frames[i_index];
+        self.reader.seek(SeekFrom::Start(frame.offset + FRAME_HEADER_SIZE))?;
+        let mut payload = Vec::new();
+        (&mut self.reader).take(frame.payload_size).read_to_end(&mut payload)?;
+        let mut payload = &payload[..];
+
+        let point_count = read_u64(&mut payload)? as usize;
+        if frame.keyframe {
+            if payload.len() < point_count.saturating_mul(12) {
+                return Err(Error::Malformed(format!("frame {} holds less than {} points", i_index, point_count)));
+            }
+            cloud.points = (0..point_count)
+                .map(|_| read_point(&mut payload))
+                .collect::<Result<_>>()?;
+        } else {
+            if point_count != cloud.points.len() {
+                return Err(Error::Malformed(format!("delta frame {} changes the point count", i_index)));
+            }
+            let changed = read_u64(&mut payload)?;
+            for _ in 0..changed {
+                let index = read_u64(&mut payload)? as usize;
+                let point = read_point(&mut payload)?;
+                *cloud.points.get_mut(index)
+                    .ok_or_else(|| Error::Malformed(format!("delta frame {} changes point {} out of range", i_index, index)))? = point;
+            }
+        }
+
+        let mut flag = [0u8; 1];
+        payload.read_exact(&mut flag)?;
+        if flag[0] != 0 {
+            cloud.attributes = attributes::read_attributes(&mut payload, point_count)?;
+        } else if frame.keyframe {
+            return Err(Error::Malformed(format!("keyframe {} without attributes", i_index)));
+        }
+        Ok(())
+    }
+}
+
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+
+fn read_point<R: Read>(reader: &mut R) -> Result<Point3<f32>> {
+    let mut buffer = [0u8; 12];
+    reader.read_exact(&mut buffer)?;
+    let value = |index: usize| f32::from_le_bytes(buffer[index * 4..index * 4 + 4].try_into().unwrap());
+    Ok(Point3::new(value(0), value(1), value(2)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AttributeData;
+
+    fn frame(step: usize) -> PointCloud {
+        // A hundred points, a few moving at each step.
+        let points = (0..100)
+            .map(|index| match index % 10 == step % 10 {
+                true => Point3::new(index as f32, step as f32, 0.),
+                false => Point3::new(index as f32, 0., 0.),
+            })
+            .collect();
+        let mut cloud = PointCloud::new(points);
+        cloud.set_attribute("energy", AttributeData::ScalarF32(vec![(step / 4) as f32; 100])).unwrap();
+        cloud
+    }
+
+    #[test]
+    fn timeline_test() {
+        let test_path = std::env::temp_dir().join("ps_data_layer_timeline.pstl");
+        let options = TimelineOptions { keyframe_interval: 4 };
+
+        let mut writer = TimelineWriter::create(&test_path, options).unwrap();
+        for step in 0..6 {
+            assert_eq!(step, writer.push(&frame(step)).unwrap());
+        }
+        drop(writer);
+
+        // Appending later, with a different point count.
+        let mut writer = TimelineWriter::append(&test_path, options).unwrap();
+        assert_eq!(6, writer.len());
+        writer.push(&frame(6)).unwrap();
+        writer.push(&PointCloud::new(vec![Point3::new(1., 2., 3.)])).unwrap();
+        drop(writer);
+
+        let mut reader = TimelineReader::open(&test_path).unwrap();
+        assert_eq!(8, reader.len());
+        let keyframes: Vec<bool> = reader.frames().iter().map(|frame| frame.keyframe).collect();
+        assert_eq!(vec![true, false, false, false, true, false, false, true], keyframes);
+        for step in (0..7).rev() {
+            let cloud = reader.read_frame(step).unwrap();
+            assert_eq!(frame(step).points, cloud.points, "Frame {}", step);
+            assert_eq!(frame(step).attributes, cloud.attributes, "Frame {}", step);
+        }
+        assert_eq!(1, reader.read_frame(7).unwrap().points.len());
+        assert!(reader.read_frame(8).is_err());
+
+        // A truncated last frame is ignored, then dropped when appending.
+        let size = std::fs::metadata(&test_path).unwrap().len();
+        OpenOptions::new().write(true).open(&test_path).unwrap().set_len(size - 5).unwrap();
+        assert_eq!(7, TimelineReader::open(&test_path).unwrap().len());
+        let mut writer = TimelineWriter::append(&test_path, options).unwrap();
+        writer.push(&frame(0)).unwrap();
+        let mut reader = TimelineReader::open(&test_path).unwrap();
+        assert_eq!(8, reader.len());
+        assert_eq!(frame(0).points, reader.read_frame(7).unwrap().points);
+    }
+}
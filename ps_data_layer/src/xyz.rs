@@ -0,0 +1,393 @@
+// ASCII XYZ / CSV import and export.
+// Each line holds one point, the columns are mapped to positions and attributes:
+//   x, y, z      -> positions
+//   r, g, b      -> Color "color" (0..255)
+//   nx, ny, nz   -> Normal "normal"
+//   intensity    -> ScalarF32 "intensity"
+// Extra columns beyond the mapping are ignored, as are empty lines and lines
+// starting with '#' or "//".
+// The mapping can also be taken from a header line of column names, as written for CSV
+// files; unknown names are skipped.
+
+use crate::{AttributeData, PointCloud};
+use kiss3d::nalgebra::Point3;
+
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::io::BufWriter;
+use crate::error::{Error, Result};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    X, Y, Z,
+    R, G, B,
+    Nx, Ny, Nz,
+    Intensity,
+    Skip,
+}
+
+impl Column {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::X => "x",
+            Column::Y => "y",
+            Column::Z => "z",
+            Column::R => "r",
+            Column::G => "g",
+            Column::B => "b",
+            Column::Nx => "nx",
+            Column::Ny => "ny",
+            Column::Nz => "nz",
+            Column::Intensity => "intensity",
+            Column::Skip => "_",
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Column> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "x" => Ok(Column::X),
+            "y" => Ok(Column::Y),
+            "z" => Ok(Column::Z),
+            "r" | "red" => Ok(Column::R),
+            "g" | "green" => Ok(Column::G),
+            "b" | "blue" => Ok(Column::B),
+            "nx" => Ok(Column::Nx),
+            "ny" => Ok(Column::Ny),
+            "nz" => Ok(Column::Nz),
+            "i" | "intensity" => Ok(Column::Intensity),
+            "_" | "skip" => Ok(Column::Skip),
+            other => Err(Error::InvalidInput(format!("unknown column {}", other))),
+        }
+    }
+}
+
+// Parses a comma separated mapping such as "x,y,z,r,g,b".
+pub fn parse_columns(mapping: &str) -> Result<Vec<Column>> {
+    mapping.split(',').map(Column::from_str).collect()
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    Whitespace,
+    Char(char),
+}
+
+
+#[derive(Debug, Clone)]
+pub struct XyzOptions {
+    pub delimiter: Delimiter,
+
+    // Lines skipped at the start of the file when reading.
+    // When writing, a header line with the column names is written if this is not zero.
+    pub skip_lines: usize,
+
+    pub columns: Vec<Column>,
+
+    // Decimals written for positions, normals and intensity.
+    // None writes the shortest representation that reads back exactly.
+    pub precision: Option<usize>,
+}
+
+impl Default for XyzOptions {
+    fn default() -> Self {
+        XyzOptions {
+            delimiter: Delimiter::Whitespace,
+            skip_lines: 0,
+            columns: vec![Column::X, Column::Y, Column::Z],
+            precision: None,
+        }
+    }
+}
+
+impl XyzOptions {
+    pub fn csv() -> XyzOptions {
+        XyzOptions { delimiter: Delimiter::Char(','), ..XyzOptions::default() }
+    }
+
+    // Columns for all the channels of the cloud that have a column mapping.
+    pub fn with_columns_for(mut self, cloud: &PointCloud) -> XyzOptions {
+        self.columns = vec![Column::X, Column::Y, Column::Z];
+        if let Some(AttributeData::Color(_)) = cloud.get_attribute("color") {
+            self.columns.extend([Column::R, Column::G, Column::B]);
+        }
+        if let Some(AttributeData::Normal(_)) = cloud.get_attribute("normal") {
+            self.columns.extend([Column::Nx, Column::Ny, Column::Nz]);
+        }
+        if let Some(AttributeData::ScalarF32(_)) | Some(AttributeData::ScalarF64(_)) = cloud.get_attribute("intensity") {
+            self.columns.push(Column::Intensity);
+        }
+        self
+    }
+
+    // Columns named by the header line of the file, if it has one mapping x, y and z.
+    // Otherwise the options are kept as they are.
+    pub fn with_header_columns(mut self, file_path: &Path) -> Result<XyzOptions> {
+        let reader = BufReader::new(File::open(file_path)?);
+        for (line_index, line) in reader.lines().enumerate().skip(self.skip_lines) {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+
+            let fields = split_fields(line, self.delimiter);
+            if fields.iter().all(|field| field.parse::<f64>().is_ok()) {
+                break;
+            }
+            let columns: Vec<Column> = fields.iter().map(|field| field.parse().unwrap_or(Column::Skip)).collect();
+            if [Column::X, Column::Y, Column::Z].iter().all(|column| columns.contains(column)) {
+                self.columns = columns;
+                self.skip_lines = line_index + 1;
+            }
+            break;
+        }
+        Ok(self)
+    }
+
+    fn has(&self, column: Column) -> bool {
+        self.columns.contains(&column)
+    }
+
+    fn has_group(&self, group: [Column; 3]) -> Result<bool> {
+        match group.iter().filter(|&&column| self.has(column)).count() {
+            0 => Ok(false),
+            3 => Ok(true),
+            _ => Err(Error::InvalidInput(format!(
+                "columns {}, {}, {} must be mapped together", group[0].name(), group[1].name(), group[2].name()))),
+        }
+    }
+}
+
+
+pub fn read_xyz(file_path: &Path, options: &XyzOptions) -> Result<PointCloud> {
+    let has_color = options.has_group([Column::R, Column::G, Column::B])?;
+    let has_normal = options.has_group([Column::Nx, Column::Ny, Column::Nz])?;
+    let has_intensity = options.has(Column::Intensity);
+    if !options.has_group([Column::X, Column::Y, Column::Z])? {
+        return Err(Error::InvalidInput("columns x, y, z are not mapped".to_string()));
+    }
+
+    let file = File::open(file_path)?;
+    let reader = BufReader::new(file);
+
+    let mut points = Vec::new();
+    let mut colors = Vec::new();
+    let mut normals = Vec::new();
+    let mut intensities = Vec::new();
+
+    let mut values = [0f64; 11];
+    for (line_index, line) in reader.lines().enumerate().skip(options.skip_lines) {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+
+        let fields = split_fields(line, options.delimiter);
+
+        // A single column names line before any point is accepted as a header.
+        let may_be_header = points.is_empty() && line_index == options.skip_lines;
+
+        if fields.len() < options.columns.len() {
+            if may_be_header {
+                continue;
+            }
+            return Err(Error::Malformed(format!(
+                "line {}: {} columns, {} expected", line_index + 1, fields.len(), options.columns.len())));
+        }
+
+        let mut parse_failed = false;
+        for (&column, field) in options.columns.iter().zip(fields.iter()) {
+            if column == Column::Skip {
+                continue;
+            }
+            match field.parse::<f64>() {
+                Ok(value) => values[column_slot(column)] = value,
+                Err(_) => parse_failed = true,
+            }
+        }
+
+        if parse_failed {
+            if may_be_header {
+                continue;
+            }
+            return Err(Error::Malformed(format!("line {}: cannot parse {}", line_index + 1, line)));
+        }
+
+        points.push(Point3::new(values[0] as f32, values[1] as f32, values[2] as f32));
+        if has_color {
+            colors.push([to_byte(values[3]), to_byte(values[4]), to_byte(values[5])]);
+        }
+        if has_normal {
+            normals.push([values[6] as f32, values[7] as f32, values[8] as f32]);
+        }
+        if has_intensity {
+            intensities.push(values[9] as f32);
+        }
+    }
+
+    let mut cloud = PointCloud::new(points);
+    if has_color {
+        cloud.set_attribute("color", AttributeData::Color(colors))?;
+    }
+    if has_normal {
+        cloud.set_attribute("normal", AttributeData::Normal(normals))?;
+    }
+    if has_intensity {
+        cloud.set_attribute("intensity", AttributeData::ScalarF32(intensities))?;
+    }
+    Ok(cloud)
+}
+
+
+pub fn write_xyz(file_path: &Path, cloud: &PointCloud, options: &XyzOptions) -> Result<usize> {
+    let colors = match cloud.get_attribute("color") {
+        Some(AttributeData::Color(colors)) => Some(colors),
+        _ => None,
+    };
+    let normals = match cloud.get_attribute("normal") {
+        Some(AttributeData::Normal(normals)) => Some(normals),
+        _ => None,
+    };
+    let intensities: Option<Vec<f64>> = match cloud.get_attribute("intensity") {
+        Some(AttributeData::ScalarF32(values)) => Some(values.iter().map(|&value| value as f64).collect()),
+        Some(AttributeData::ScalarF64(values)) => Some(values.clone()),
+        _ => None,
+    };
+
+    for &column in &options.columns {
+        let missing = match column {
+            Column::R | Column::G | Column::B => colors.is_none(),
+            Column::Nx | Column::Ny | Column::Nz => normals.is_none(),
+            Column::Intensity => intensities.is_none(),
+            _ => false,
+        };
+        if missing {
+            return Err(Error::InvalidInput(format!("no channel for column {}", column.name())));
+        }
+    }
+
+    let separator = match options.delimiter {
+        Delimiter::Whitespace => " ".to_string(),
+        Delimiter::Char(delimiter) => delimiter.to_string(),
+    };
+
+    let file = File::create(file_path)?;
+    let mut writer = BufWriter::new(file);
+
+    if options.skip_lines > 0 {
+        let names: Vec<&str> = options.columns.iter().map(Column::name).collect();
+        writeln!(writer, "{}", names.join(&separator))?;
+    }
+
+    let format_value = |value: f64, single: bool| match options.precision {
+        Some(precision) => format!("{:.*}", precision, value),
+        None if single => format!("{}", value as f32),
+        None => format!("{}", value),
+    };
+
+    let mut fields = Vec::with_capacity(options.columns.len());
+    for (index, point) in cloud.points.iter().enumerate() {
+        fields.clear();
+        for &column in &options.columns {
+            fields.push(match column {
+                Column::X => format_value(point.x as f64, true),
+                Column::Y => format_value(point.y as f64, true),
+                Column::Z => format_value(point.z as f64, true),
+                Column::R => colors.unwrap()[index][0].to_string(),
+                Column::G => colors.unwrap()[index][1].to_string(),
+                Column::B => colors.unwrap()[index][2].to_string(),
+                Column::Nx => format_value(normals.unwrap()[index][0] as f64, true),
+                Column::Ny => format_value(normals.unwrap()[index][1] as f64, true),
+                Column::Nz => format_value(normals.unwrap()[index][2] as f64, true),
+                Column::Intensity => format_value(intensities.as_ref().unwrap()[index], false),
+                Column::Skip => "0".to_string(),
+            });
+        }
+        writeln!(writer, "{}", fields.join(&separator))?;
+    }
+
+    writer.flush()?;
+    Ok(cloud.points.len())
+}
+
+
+fn split_fields(line: &str, delimiter: Delimiter) -> Vec<&str> {
+    match delimiter {
+        Delimiter::Whitespace => line.split_whitespace().collect(),
+        Delimiter::Char(delimiter) => line.split(delimiter).map(str::trim).collect(),
+    }
+}
+
+
+// Index of a column in the values buffer of a line.
+fn column_slot(column: Column) -> usize {
+    match column {
+        Column::X => 0,
+        Column::Y => 1,
+        Column::Z => 2,
+        Column::R => 3,
+        Column::G => 4,
+        Column::B => 5,
+        Column::Nx => 6,
+        Column::Ny => 7,
+        Column::Nz => 8,
+        Column::Intensity => 9,
+        Column::Skip => 10,
+    }
+}
+
+fn to_byte(value: f64) -> u8 {
+    value.round().clamp(0., 255.) as u8
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xyz_round_trip_test() {
+        let mut cloud = PointCloud::new(vec![Point3::new(0.1, -2.5, 3e-7), Point3::new(1., 2., 3.)]);
+        cloud.set_attribute("color", AttributeData::Color(vec![[10, 20, 30], [255, 0, 128]])).unwrap();
+        cloud.set_attribute("intensity", AttributeData::ScalarF32(vec![0.25, 0.75])).unwrap();
+
+        let options = XyzOptions { skip_lines: 1, ..XyzOptions::csv() }.with_columns_for(&cloud);
+        let test_path = std::env::temp_dir().join("ps_data_layer_points.csv");
+        assert_eq!(2, write_xyz(&test_path, &cloud, &options).expect("Writing failed"));
+
+        let read_cloud = read_xyz(&test_path, &options).expect("Reading failed");
+        assert_eq!(cloud.points, read_cloud.points);
+        assert_eq!(cloud.attributes, read_cloud.attributes);
+
+        // The header line gives the mapping back.
+        let header_options = XyzOptions::csv().with_header_columns(&test_path).unwrap();
+        assert_eq!(options.columns, header_options.columns);
+        assert_eq!(cloud.attributes, read_xyz(&test_path, &header_options).unwrap().attributes);
+    }
+
+    #[test]
+    fn xyz_column_mapping_test() {
+        let text = "x y z\n# comment\n1 2 3 9 0.5\n\n4 5 6 9 0.25 extra\n";
+        let test_path = std::env::temp_dir().join("ps_data_layer_points.xyz");
+        std::fs::write(&test_path, text).unwrap();
+
+        let options = XyzOptions { columns: parse_columns("x,y,z,skip,intensity").unwrap(), ..XyzOptions::default() };
+        let cloud = read_xyz(&test_path, &options).expect("Reading failed");
+        assert_eq!(vec![Point3::new(1., 2., 3.), Point3::new(4., 5., 6.)], cloud.points);
+        assert_eq!(Some(&AttributeData::ScalarF32(vec![0.5, 0.25])), cloud.get_attribute("intensity"));
+
+        let partial = XyzOptions { columns: parse_columns("x,y,z,r").unwrap(), ..XyzOptions::default() };
+        assert!(read_xyz(&test_path, &partial).is_err(), "Partial color mapping accepted");
+    }
+}
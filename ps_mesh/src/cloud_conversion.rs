@@ -0,0 +1,169 @@
+// Conversions between the simulation mesh (f64) and the data layer point cloud (f32).
+// Mapping of the mesh state on the cloud attribute channels:
+//   points          positions narrowed to f32; to_cloud_exact() also keeps the exact
+//                   values in the "x_f64", "y_f64", "z_f64" channels
+//   energies        "energy" channel
+//   breaking_point  "broken" label channel (1 when broken) and "breaking_threshold"
+//                   channel (0 when broken)
+// Precision policy: converting a mesh to a cloud and back is lossless for energies and
+// breaking points, and for positions through to_cloud_exact(). The exact channels
+// triple the size of the points, so they are left out of the plain conversion used
+// for publishing and exports. The f64 channels are only used when they still
+// narrow to the cloud positions; if the positions were changed in f32 since, they
+// are widened from f32 instead. Clouds without the channels are widened from f32.
+// Energies and breaking points are only mapped when the mesh has one per point.
+// Connections are not mapped, save the mesh file (mesh_io.rs) to keep them.
+
+use super::point_mesh::{BreakingPoint, PointsMesh};
+use nalgebra::Point3;
+
+use ps_data_layer::{Attribute, AttributeData, Error, PointCloud, Result};
+
+pub const POSITION_CHANNELS: [&str; 3] = ["x_f64", "y_f64", "z_f64"];
+pub const ENERGY_CHANNEL: &str = "energy";
+pub const BROKEN_CHANNEL: &str = "broken";
+pub const THRESHOLD_CHANNEL: &str = "breaking_threshold";
+
+
+impl PointsMesh {
+    // With the exact positions, for lossless saves.
+    pub fn to_cloud_exact(&self) -> PointCloud {
+        let mut cloud = PointCloud::from(self);
+        let exact = POSITION_CHANNELS.iter().enumerate().map(|(axis, name)| {
+            Attribute::new(name, AttributeData::ScalarF64(self.points.iter().map(|point| point[axis]).collect()))
+        });
+        cloud.attributes.splice(0..0, exact);
+        cloud
+    }
+}
+
+
+impl From<&PointsMesh> for PointCloud {
+    fn from(mesh: &PointsMesh) -> PointCloud {
+        let mut cloud = PointCloud::new(mesh.points.iter()
+            .map(|point| ps_data_layer::Point3::new(point.x as f32, point.y as f32, point.z as f32))
+            .collect());
+
+        if mesh.energies.len() == mesh.points.len() {
+            cloud.attributes.push(Attribute::new(ENERGY_CHANNEL, AttributeData::ScalarF64(mesh.energies.clone())));
+        }
+
+        if mesh.breaking_point.len() == mesh.points.len() {
+            let (broken, thresholds) = mesh.breaking_point.iter()
+                .map(|breaking_point| match breaking_point {
+                    BreakingPoint::Broken => (1, 0.),
+                    BreakingPoint::Intact(threshold) => (0, *threshold),
+                })
+                .unzip();
+            cloud.attributes.push(Attribute::new(BROKEN_CHANNEL, AttributeData::Label(broken)));
+            cloud.attributes.push(Attribute::new(THRESHOLD_CHANNEL, AttributeData::ScalarF64(thresholds)));
+        }
+
+        cloud
+    }
+}
+
+
+impl TryFrom<&PointCloud> for PointsMesh {
+    type Error = Error;
+
+    fn try_from(cloud: &PointCloud) -> Result<PointsMesh> {
+        let mut mesh = PointsMesh::new();
+
+        let exact = [
+            scalar_f64_channel(cloud, POSITION_CHANNELS[0])?,
+            scalar_f64_channel(cloud, POSITION_CHANNELS[1])?,
+            scalar_f64_channel(cloud, POSITION_CHANNELS[2])?,
+        ];
+        mesh.points = cloud.points.iter()
+            .enumerate()
+            .map(|(index, point)| match exact {
+                [Some(x), Some(y), Some(z)] if narrows_to(&[x[index], y[index], z[index]], point) =>
+                    Point3::new(x[index], y[index], z[index]),
+                _ => Point3::new(point.x as f64, point.y as f64, point.z as f64),
+            })
+            .collect();
+
+        if let Some(energies) = scalar_f64_channel(cloud, ENERGY_CHANNEL)? {
+            mesh.energies = energies.to_vec();
+        }
+
+        let broken = match cloud.get_attribute(BROKEN_CHANNEL) {
+            Some(AttributeData::Label(values)) if values.len() == cloud.points.len() => Some(values),
+            Some(_) => return Err(wrong_kind(BROKEN_CHANNEL)),
+            None => None,
+        };
+        if let (Some(broken), Some(thresholds)) = (broken, scalar_f64_channel(cloud, THRESHOLD_CHANNEL)?) {
+            mesh.breaking_point = broken.iter()
+                .zip(thresholds)
+                .map(|(&broken, &threshold)| match broken {
+                    0 => BreakingPoint::Intact(threshold),
+                    _ => BreakingPoint::Broken,
+                })
+                .collect();
+        }
+
+        Ok(mesh)
+    }
+}
+
+
+fn scalar_f64_channel<'a>(cloud: &'a PointCloud, name: &str) -> Result<Option<&'a [f64]>> {
+    match cloud.get_attribute(name) {
+        Some(AttributeData::ScalarF64(values)) if values.len() == cloud.points.len() => Ok(Some(values)),
+        Some(_) => Err(wrong_kind(name)),
+        None => Ok(None),
+    }
+}
+
+
+fn wrong_kind(name: &str) -> Error {
+    Error::InvalidInput(format!("channel {} has an unexpected kind or length", name))
+}
+
+
+fn narrows_to(exact: &[f64; 3], point: &ps_data_layer::Point3<f32>) -> bool {
+    exact[0] as f32 == point.x && exact[1] as f32 == point.y && exact[2] as f32 == point.z
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mesh_cloud_round_trip_test() {
+        let mut mesh = PointsMesh::new();
+        mesh.points = vec![Point3::new(0.1, 1. / 3., -7.000000001), Point3::new(1e-12, 2., 3.)];
+        mesh.energies = vec![0.5, 1e-300];
+        mesh.breaking_point = vec![BreakingPoint::Broken, BreakingPoint::Intact(0.25)];
+
+        // Through the points file, as the CLI and the visualizer share it.
+        let test_path = std::env::temp_dir().join("ps_mesh_conversion.bin");
+        mesh.to_cloud_exact().write_to_file(&test_path).expect("Writing failed");
+        let cloud = PointCloud::new_from_file(&test_path).expect("Reading failed");
+
+        let loaded = PointsMesh::try_from(&cloud).expect("Conversion failed");
+        assert_eq!(mesh.points, loaded.points);
+        assert_eq!(mesh.energies, loaded.energies);
+        assert_eq!(mesh.breaking_point, loaded.breaking_point);
+
+        // Positions moved in f32 win over the stale f64 channels.
+        let mut moved = cloud;
+        moved.points[1].x = 10.;
+        let loaded = PointsMesh::try_from(&moved).unwrap();
+        assert_eq!(mesh.points[0], loaded.points[0]);
+        assert_eq!(Point3::new(10., 2., 3.), loaded.points[1]);
+
+        // Without the exact channels, positions are widened from f32.
+        let plain = PointCloud::from(&mesh);
+        assert_eq!(vec![ENERGY_CHANNEL, BROKEN_CHANNEL, THRESHOLD_CHANNEL], plain.attribute_names());
+        let loaded = PointsMesh::try_from(&plain).unwrap();
+        assert_eq!(Point3::new(0.1f32 as f64, (1. / 3.) as f32 as f64, -7.), loaded.points[0]);
+        assert_eq!(mesh.energies, loaded.energies);
+
+        // Channels of the wrong kind are rejected.
+        moved.set_attribute(ENERGY_CHANNEL, AttributeData::Label(vec![0, 1])).unwrap();
+        assert!(PointsMesh::try_from(&moved).is_err());
+    }
+}
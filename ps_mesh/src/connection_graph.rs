@@ -0,0 +1,274 @@
+// Undirected graph between the points of a mesh, in compressed sparse row form.
+// The neighbours of point i are neighbours[offsets[i]..offsets[i] + degrees[i]], sorted
+// by index; the rest of the row, up to offsets[i + 1], holds the slots freed by removed
+// edges. Each edge is in the rows of both its points, and its slots point to its data
+// in edge_data, stored once per edge: removing an edge shifts the two rows and leaves
+// None behind.
+// Building the graph (from_edges, retain_points) compacts the arrays.
+// With the serde feature, the graph is serialized as its point count and edge list.
+
+use ps_data_layer::{Error, Result};
+
+use std::ops::Range;
+
+
+#[derive(Debug, Clone)]
+pub struct ConnectionGraph<E = ()> {
+    offsets: Vec<usize>,
+    degrees: Vec<usize>,
+    neighbours: Vec<usize>,
+    // Index in edge_data of the edge in the same slot of neighbours
+    edge_slots: Vec<usize>,
+    edge_data: Vec<Option<E>>,
+    edge_count: usize,
+}
+
+
+impl<E> Default for ConnectionGraph<E> {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+
+impl<E> ConnectionGraph<E> {
+    // Points without connections.
+    pub fn new(point_count: usize) -> Self {
+        Self {
+            offsets: vec![0; point_count + 1],
+            degrees: vec![0; point_count],
+            neighbours: Vec::new(),
+            edge_slots: Vec::new(),
+            edge_data: Vec::new(),
+            edge_count: 0,
+        }
+    }
+
+    // Self loops are skipped, a pair given twice keeps its first data.
+    pub fn from_edges<I: IntoIterator<Item = (usize, usize, E)>>(point_count: usize, edges: I) -> Result<Self> {
+        let mut pairs = Vec::new();
+        for (a, b, data) in edges {
+            if a >= point_count || b >= point_count {
+                return Err(Error::InvalidInput(format!("edge {}-{} out of {} points", a, b, point_count)));
+            }
+            if a != b {
+                pairs.push((a.min(b), a.max(b), data));
+            }
+        }
+        Ok(Self::build(point_count, pairs))
+    }
+
+    fn build(point_count: usize, mut pairs: Vec<(usize, usize, E)>) -> Self {
+        // Stable sort, so that dedup keeps the first data.
+        pairs.sort_by_key(|&(a, b, _)| (a, b));
+        pairs.dedup_by_key(|&mut (a, b, _)| (a, b));
+
+        let mut degrees = vec![0; point_count];
+        for &(a, b, _) in &pairs {
+            degrees[a] += 1;
+            degrees[b] += 1;
+        }
+        let mut offsets = Vec::with_capacity(point_count + 1);
+        offsets.push(0);
+        for &degree in &degrees {
+            offsets.push(offsets[offsets.len() - 1] + degree);
+        }
+
+        // Walking the pairs in order fills each row with the lower neighbours, then the higher ones.
+        let mut neighbours = vec![0; pairs.len() * 2];
+        let mut edge_slots = vec![0; pairs.len() * 2];
+        let mut cursors = offsets[..point_count].to_vec();
+        for (edge, &(a, b, _)) in pairs.iter().enumerate() {
+            for (from, to) in [(a, b), (b, a)] {
+                neighbours[cursors[from]] = to;
+                edge_slots[cursors[from]] = edge;
+                cursors[from] += 1;
+            }
+        }
+
+        Self {
+            offsets,
+            degrees,
+            neighbours,
+            edge_slots,
+            edge_count: pairs.len(),
+            edge_data: pairs.into_iter().map(|(_, _, data)| Some(data)).collect(),
+        }
+    }
+
+    pub fn point_count(&self) -> usize {
+        self.degrees.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edge_count
+    }
+
+    fn row(&self, point: usize) -> Range<usize> {
+        self.offsets[point]..self.offsets[point] + self.degrees[point]
+    }
+
+    // Sorted by index. Panics when the point is out of the graph.
+    pub fn neighbours(&self, point: usize) -> &[usize] {
+        &self.neighbours[self.row(point)]
+    }
+
+    pub fn degree(&self, point: usize) -> usize {
+        self.degrees[point]
+    }
+
+    fn slot(&self, a: usize, b: usize) -> Option<usize> {
+        if a >= self.point_count() || b >= self.point_count() {
+            return None;
+        }
+        let row = self.row(a);
+        self.neighbours[row.clone()].binary_search(&b).ok().map(|position| row.start + position)
+    }
+
+    pub fn contains_edge(&self, a: usize, b: usize) -> bool {
+        self.slot(a, b).is_some()
+    }
+
+    pub fn edge(&self, a: usize, b: usize) -> Option<&E> {
+        self.slot(a, b).and_then(|slot| self.edge_data[self.edge_slots[slot]].as_ref())
+    }
+
+    pub fn edge_mut(&mut self, a: usize, b: usize) -> Option<&mut E> {
+        let slot = self.slot(a, b)?;
+        self.edge_data[self.edge_slots[slot]].as_mut()
+    }
+
+    // The neighbours of the point with the data of the edges to them.
+    pub fn neighbour_edges(&self, point: usize) -> impl Iterator<Item = (usize, &E)> + '_ {
+        self.row(point).filter_map(|slot| Some((self.neighbours[slot], self.edge_data[self.edge_slots[slot]].as_ref()?)))
+    }
+
+    // Each edge once, as (a, b, data) with a < b, sorted.
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize, &E)> + '_ {
+        (0..self.point_count()).flat_map(move |a| {
+            self.neighbour_edges(a).filter(move |&(b, _)| b > a).map(move |(b, data)| (a, b, data))
+        })
+    }
+
+    // O(degree) for both points: the rows are shifted to stay sorted.
+    pub fn remove_edge(&mut self, a: usize, b: usize) -> Option<E> {
+        let slot_a = self.slot(a, b)?;
+        let slot_b = self.slot(b, a)?;
+        let edge = self.edge_slots[slot_a];
+
+        for (point, slot) in [(a, slot_a), (b, slot_b)] {
+            let end = self.offsets[point] + self.degrees[point];
+            self.neighbours.copy_within(slot + 1..end, slot);
+            self.edge_slots.copy_within(slot + 1..end, slot);
+            self.degrees[point] -= 1;
+        }
+        self.edge_count -= 1;
+        self.edge_data[edge].take()
+    }
+
+    // Keeps the points passing the test, with the edges between them.
+    // Returns the new index of each old point, None for the removed ones.
+    pub fn retain_points<F: Fn(usize) -> bool>(&mut self, keep: F) -> Vec<Option<usize>> {
+        let mut mapping = Vec::with_capacity(self.point_count());
+        let mut kept = 0;
+        for point in 0..self.point_count() {
+            mapping.push(keep(point).then(|| {
+                kept += 1;
+                kept - 1
+            }));
+        }
+
+        let mut pairs = Vec::new();
+        for a in 0..self.point_count() {
+            for slot in self.row(a) {
+                let b = self.neighbours[slot];
+                if let (true, Some(new_a), Some(new_b)) = (b > a, mapping[a], mapping[b]) {
+                    if let Some(data) = self.edge_data[self.edge_slots[slot]].take() {
+                        pairs.push((new_a, new_b, data));
+                    }
+                }
+            }
+        }
+
+        *self = Self::build(kept, pairs);
+        mapping
+    }
+}
+
+
+impl<E: Default> ConnectionGraph<E> {
+    pub fn from_pairs<I: IntoIterator<Item = (usize, usize)>>(point_count: usize, pairs: I) -> Result<Self> {
+        Self::from_edges(point_count, pairs.into_iter().map(|(a, b)| (a, b, E::default())))
+    }
+}
+
+
+// Same points and same edges, whatever the removals that led there.
+impl<E: PartialEq> PartialEq for ConnectionGraph<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.point_count() == other.point_count() && self.edges().eq(other.edges())
+    }
+}
+
+
+#[cfg(feature = "serde")]
+mod serialization {
+    use super::ConnectionGraph;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize)]
+    struct EdgeList<'a, E> {
+        point_count: usize,
+        edges: Vec<(usize, usize, &'a E)>,
+    }
+
+    #[derive(Deserialize)]
+    struct OwnedEdgeList<E> {
+        point_count: usize,
+        edges: Vec<(usize, usize, E)>,
+    }
+
+    impl<E: Serialize> Serialize for ConnectionGraph<E> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            EdgeList { point_count: self.point_count(), edges: self.edges().collect() }.serialize(serializer)
+        }
+    }
+
+    impl<'de, E: Deserialize<'de>> Deserialize<'de> for ConnectionGraph<E> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let list = OwnedEdgeList::deserialize(deserializer)?;
+            ConnectionGraph::from_edges(list.point_count, list.edges).map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graph_test() {
+        let mut graph = ConnectionGraph::from_edges(5, [(3, 0, 'a'), (0, 1, 'b'), (1, 3, 'c'), (1, 0, 'x'), (2, 2, 'y'), (1, 4, 'd')]).unwrap();
+        assert_eq!((5, 4), (graph.point_count(), graph.edge_count()));
+        assert_eq!(&[1, 3], graph.neighbours(0));
+        assert_eq!(&[0, 3, 4], graph.neighbours(1));
+        assert!(graph.neighbours(2).is_empty());
+        assert_eq!(Some(&'b'), graph.edge(1, 0));
+        assert_eq!(vec![(0, 1, &'b'), (0, 3, &'a'), (1, 3, &'c'), (1, 4, &'d')], graph.edges().collect::<Vec<_>>());
+
+        *graph.edge_mut(3, 1).unwrap() = 'C';
+        assert_eq!(Some('b'), graph.remove_edge(1, 0));
+        assert_eq!(None, graph.remove_edge(0, 1));
+        assert_eq!(&[3, 4], graph.neighbours(1));
+        assert_eq!(vec![(3, &'C'), (4, &'d')], graph.neighbour_edges(1).collect::<Vec<_>>());
+        assert_eq!(ConnectionGraph::from_edges(5, [(0, 3, 'a'), (1, 3, 'C'), (1, 4, 'd')]).unwrap(), graph);
+
+        // Dropping the unconnected point 2, and point 0.
+        let mapping = graph.retain_points(|point| point != 0 && point != 2);
+        assert_eq!(vec![None, Some(0), None, Some(1), Some(2)], mapping);
+        assert_eq!(vec![(0, 1, &'C'), (0, 2, &'d')], graph.edges().collect::<Vec<_>>());
+
+        assert!(ConnectionGraph::<()>::from_pairs(2, [(0, 2)]).is_err());
+    }
+}
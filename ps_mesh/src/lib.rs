@@ -1,2 +1,9 @@
 pub mod ps_creation;
-pub mod point_mesh;
\ No newline at end of file
+pub mod point_mesh;
+pub mod spatial_index;
+pub mod connection_graph;
+pub mod mesh_io;
+pub mod cloud_conversion;
+pub mod obj_export;
+pub mod triangle_mesh;
+pub mod stl_sampling;
\ No newline at end of file
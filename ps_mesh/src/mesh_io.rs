@@ -0,0 +1,286 @@
+// Mesh file format, saving the whole simulation state so that runs can be resumed.
+// Layout (little endian):
+//   magic             [u8; 4]  "PSMS"
+//   version           u16
+//   header size       u16      bytes from the start of the file to the points
+//   point count       u64
+//   energy count      u64
+//   breaking count    u64
+//   neighbour count   u64      total length of the adjacency lists
+//   points            3 x f64 per point
+//   energies          f64 per energy
+//   breaking points   u8 state (0 broken, 1 intact) + f64 threshold (0 when broken)
+//   adjacency offsets u64 per point + 1, where the neighbours of each point start
+//   neighbours        u64 per neighbour
+//   bonds             per connection (neighbour count / 2), in increasing (a, b) order with a < b:
+//                     rest length f64, stiffness f64, damage f64, material pair 2 x u16
+// Version 1 files have no bonds: they are loaded at rest at the current distances.
+// Energies and breaking points are stored with their own counts, as the simulation
+// does not always fill them for every point. Each connection is listed from both its
+// points, the neighbours of a point in increasing order.
+
+use super::point_mesh::{Bond, BreakingPoint, PointsMesh};
+use super::connection_graph::ConnectionGraph;
+use nalgebra::Point3;
+
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+use std::io::BufReader;
+use std::io::Write;
+
+use ps_data_layer::{Error, Result, WriteOptions};
+use ps_data_layer::atomic;
+
+pub const MAGIC: [u8; 4] = *b"PSMS";
+pub const FORMAT_VERSION: u16 = 2;
+pub const BOND_SIZE: u64 = 28;
+pub const HEADER_SIZE: u16 = 40;
+
+
+// Writing
+
+pub fn write_mesh(file_path: &Path, mesh: &PointsMesh, options: &WriteOptions) -> Result<usize> {
+    println!("writing mesh file {}", file_path.display());
+
+    // The graph rows without their free slots.
+    if mesh.connections.edge_count() > 0 && mesh.connections.point_count() != mesh.points.len() {
+        return Err(Error::InvalidInput(format!(
+            "connections between {} points for {} points", mesh.connections.point_count(), mesh.points.len())));
+    }
+    let mut offsets = Vec::with_capacity(mesh.points.len() + 1);
+    let mut neighbours = Vec::new();
+    offsets.push(0u64);
+    for index in 0..mesh.points.len() {
+        if let Some(connections) = mesh.get_point_connections(index) {
+            neighbours.extend(connections.iter().map(|&neighbour| neighbour as u64));
+        }
+        offsets.push(neighbours.len() as u64);
+    }
+
+    atomic::write_atomically(file_path, options, |writer| {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&HEADER_SIZE.to_le_bytes())?;
+        for count in [mesh.points.len(), mesh.energies.len(), mesh.breaking_point.len(), neighbours.len()] {
+            writer.write_all(&(count as u64).to_le_bytes())?;
+        }
+
+        for point in &mesh.points {
+            for value in [point.x, point.y, point.z] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        for energy in &mesh.energies {
+            writer.write_all(&energy.to_le_bytes())?;
+        }
+        for breaking_point in &mesh.breaking_point {
+            let (state, threshold) = match breaking_point {
+                BreakingPoint::Broken => (0u8, 0.),
+                BreakingPoint::Intact(threshold) => (1u8, *threshold),
+            };
+            writer.write_all(&[state])?;
+            writer.write_all(&threshold.to_le_bytes())?;
+        }
+        for value in offsets.iter().chain(neighbours.iter()) {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for (_, _, bond) in mesh.bonds() {
+            for value in [bond.rest_length, bond.stiffness, bond.damage] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            for material in bond.material_pair {
+                writer.write_all(&material.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    })?;
+
+    println!("Written {} points and {} connections.", mesh.points.len(), neighbours.len());
+
+    Ok(mesh.points.len())
+}
+
+
+// Reading
+
+pub fn read_mesh(file_path: &Path) -> Result<PointsMesh> {
+    let file = File::open(file_path)?;
+    let file_size = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0u8; HEADER_SIZE as usize];
+    reader.read_exact(&mut header)?;
+    if header[0..4] != MAGIC {
+        return Err(Error::BadHeader("not a mesh file".to_string()));
+    }
+
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let header_size = u16::from_le_bytes([header[6], header[7]]);
+    if header_size < HEADER_SIZE {
+        return Err(Error::BadHeader(format!("header size {} is too small", header_size)));
+    }
+
+    let count = |index: usize| u64::from_le_bytes(header[8 + index * 8..16 + index * 8].try_into().unwrap());
+    let (point_count, energy_count, breaking_count, neighbour_count) = (count(0), count(1), count(2), count(3));
+
+    // Checking the size upfront, so that a corrupted count does not trigger a huge allocation.
+    let expected_size = [
+        header_size as u64,
+        point_count.saturating_mul(24),
+        energy_count.saturating_mul(8),
+        breaking_count.saturating_mul(9),
+        point_count.saturating_add(1).saturating_mul(8),
+        neighbour_count.saturating_mul(8),
+        if version >= 2 { (neighbour_count / 2).saturating_mul(BOND_SIZE) } else { 0 },
+    ].iter().fold(0u64, |total, &size| total.saturating_add(size));
+    if file_size < expected_size {
+        return Err(Error::Truncated { expected: expected_size, found: file_size });
+    }
+    std::io::copy(&mut reader.by_ref().take((header_size - HEADER_SIZE) as u64), &mut std::io::sink())?;
+
+    let mut mesh = PointsMesh::new();
+    mesh.points = (0..point_count)
+        .map(|_| Ok(Point3::new(read_f64(&mut reader)?, read_f64(&mut reader)?, read_f64(&mut reader)?)))
+        .collect::<Result<_>>()?;
+    mesh.energies = (0..energy_count)
+        .map(|_| read_f64(&mut reader))
+        .collect::<Result<_>>()?;
+    mesh.breaking_point = (0..breaking_count)
+        .map(|_| {
+            let mut state = [0u8; 1];
+            reader.read_exact(&mut state)?;
+            let threshold = read_f64(&mut reader)?;
+            match state[0] {
+                0 => Ok(BreakingPoint::Broken),
+                1 => Ok(BreakingPoint::Intact(threshold)),
+                other => Err(Error::Malformed(format!("unknown breaking point state {}", other))),
+            }
+        })
+        .collect::<Result<_>>()?;
+
+    let offsets = (0..=point_count)
+        .map(|_| read_u64(&mut reader))
+        .collect::<Result<Vec<_>>>()?;
+    let neighbours = (0..neighbour_count)
+        .map(|_| read_u64(&mut reader))
+        .collect::<Result<Vec<_>>>()?;
+    let pairs = connections_from_adjacency(&offsets, &neighbours, point_count)?;
+
+    let bonds = pairs.iter()
+        .map(|&(a, b)| match version {
+            1 => Ok(Bond::new(nalgebra::distance(&mesh.points[a], &mesh.points[b]))),
+            _ => {
+                let (rest_length, stiffness, damage) = (read_f64(&mut reader)?, read_f64(&mut reader)?, read_f64(&mut reader)?);
+                let mut materials = [0u8; 4];
+                reader.read_exact(&mut materials)?;
+                let material_pair = [u16::from_le_bytes([materials[0], materials[1]]), u16::from_le_bytes([materials[2], materials[3]])];
+                Ok(Bond { rest_length, stiffness, damage, material_pair })
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    mesh.connections = ConnectionGraph::from_edges(point_count as usize, pairs.into_iter().zip(bonds).map(|((a, b), bond)| (a, b, bond)))?;
+
+    Ok(mesh)
+}
+
+
+// The connected pairs (a, b) with a < b, sorted.
+fn connections_from_adjacency(offsets: &[u64], neighbours: &[u64], point_count: u64) -> Result<Vec<(usize, usize)>> {
+    if offsets.first() != Some(&0) || offsets.last() != Some(&(neighbours.len() as u64))
+        || offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+        return Err(Error::Malformed("inconsistent adjacency offsets".to_string()));
+    }
+    if let Some(neighbour) = neighbours.iter().find(|&&neighbour| neighbour >= point_count) {
+        return Err(Error::Malformed(format!("connection to point {} out of {} points", neighbour, point_count)));
+    }
+
+    let row = |index: usize| &neighbours[offsets[index] as usize..offsets[index + 1] as usize];
+    let pairs = (0..point_count as usize)
+        .flat_map(|index| row(index).iter().map(move |&neighbour| (index, neighbour as usize)));
+    let graph = ConnectionGraph::<()>::from_pairs(point_count as usize, pairs)?;
+
+    // Each connection must be listed from both points, once, in increasing order.
+    if let Some(index) = (0..point_count as usize).find(|&index| graph.neighbours(index).iter().map(|&neighbour| neighbour as u64).ne(row(index).iter().copied())) {
+        return Err(Error::Malformed(format!("connections of point {} not symmetric or not sorted", index)));
+    }
+    Ok(graph.edges().map(|(a, b, _)| (a, b)).collect())
+}
+
+
+fn read_f64<R: Read>(reader: &mut R) -> Result<f64> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(f64::from_le_bytes(buffer))
+}
+
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mesh_round_trip_test() {
+        let test_path = std::env::temp_dir().join("ps_mesh_state.psm");
+
+        let mut mesh = PointsMesh::new();
+        mesh.points = vec![Point3::new(0.1, 0.2, 0.3), Point3::new(1. / 3., -2.5e-12, 7.), Point3::new(5., 5., 5.), Point3::new(0.15, 0.2, 0.3)];
+        mesh.energies = vec![1.5, -0.25, 0., 1e300];
+        mesh.breaking_point = vec![BreakingPoint::Intact(0.75), BreakingPoint::Broken, BreakingPoint::Intact(2.)];
+        mesh.establish_connections(0.1);
+        assert_eq!(1, mesh.connections.edge_count());
+        let bond = Bond { rest_length: 1. / 3., stiffness: 250., damage: 0.125, material_pair: [3, 65535] };
+        mesh.connections = ConnectionGraph::from_edges(4, [(0, 3, bond), (1, 2, Bond::new(2.))]).unwrap();
+
+        write_mesh(&test_path, &mesh, &WriteOptions::default()).expect("Writing failed");
+        let loaded = read_mesh(&test_path).expect("Reading failed");
+
+        // Bit-exact positions and energies.
+        assert_eq!(mesh.points, loaded.points);
+        assert_eq!(mesh.energies, loaded.energies);
+        assert_eq!(mesh.breaking_point, loaded.breaking_point);
+        assert_eq!(mesh.connections, loaded.connections);
+
+        // Truncated files and corrupted adjacency.
+        let bytes = std::fs::read(&test_path).unwrap();
+        std::fs::write(&test_path, &bytes[..bytes.len() - 8]).unwrap();
+        assert!(matches!(read_mesh(&test_path), Err(Error::Truncated { .. })));
+
+        let mut corrupted = bytes.clone();
+        let last = corrupted.len() - 2 * BOND_SIZE as usize - 8;
+        corrupted[last..last + 8].copy_from_slice(&99u64.to_le_bytes());
+        std::fs::write(&test_path, &corrupted).unwrap();
+        assert!(matches!(read_mesh(&test_path), Err(Error::Malformed(_))));
+
+        // Version 1 files, without bonds.
+        let mut version_1 = bytes[..bytes.len() - 2 * BOND_SIZE as usize].to_vec();
+        version_1[4..6].copy_from_slice(&1u16.to_le_bytes());
+        std::fs::write(&test_path, &version_1).unwrap();
+        let loaded = read_mesh(&test_path).expect("Reading version 1 failed");
+        let rest_length = nalgebra::distance(&mesh.points[0], &mesh.points[3]);
+        assert_eq!(Some(&Bond::new(rest_length)), loaded.bond(0, 3));
+        assert_eq!(2, loaded.connections.edge_count());
+
+        // Removing unconnected points keeps the per-point data aligned.
+        mesh.break_bond(1, 2);
+        mesh.remove_unconnected_points();
+        assert_eq!(vec![1.5, 1e300], mesh.energies);
+        assert_eq!(3, mesh.breaking_point.len(), "Breaking points without one per point changed");
+        write_mesh(&test_path, &mesh, &WriteOptions::default()).expect("Writing failed");
+        let loaded = read_mesh(&test_path).expect("Reading failed");
+        assert_eq!(2, loaded.points.len());
+        assert_eq!(mesh.points, loaded.points);
+        assert_eq!(mesh.energies, loaded.energies);
+        assert_eq!(Some(&bond), loaded.bond(0, 1));
+    }
+}
@@ -0,0 +1,61 @@
+// Wavefront OBJ export of a PointsMesh, for viewing in external tools.
+// The points are written as vertices, with full f64 precision. Optionally:
+//   - the connections graph as line elements, one per connected pair
+//   - triangle faces indexing the points, given by the caller (PointsMesh::write_obj
+//     has none, surfaces in a TriangleMesh are exported with TriangleMesh::write_obj)
+// The simulation state (energies, breaking points) is not part of the export,
+// use mesh_io.rs to save it.
+
+use super::point_mesh::PointsMesh;
+
+use std::path::Path;
+
+use ps_data_layer::Result;
+use ps_data_layer::obj::{self, ObjData};
+
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ObjExportOptions {
+    pub connections: bool,
+}
+
+
+pub fn write_mesh_obj(file_path: &Path, mesh: &PointsMesh, options: &ObjExportOptions, faces: &[[usize; 3]]) -> Result<usize> {
+    let data = ObjData {
+        vertices: mesh.points.iter().map(|point| [point.x, point.y, point.z]).collect(),
+        colors: None,
+        lines: match options.connections {
+            true => mesh.connections.edges().map(|(a, b, _)| [a, b]).collect(),
+            false => Vec::new(),
+        },
+        faces: faces.iter().map(|face| face.to_vec()).collect(),
+    };
+    obj::write_obj(file_path, &data)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point3;
+
+    #[test]
+    fn mesh_obj_test() {
+        let test_path = std::env::temp_dir().join("ps_mesh_export.obj");
+        let mut mesh = PointsMesh::new();
+        mesh.points = vec![Point3::new(0., 0., 0.), Point3::new(0.5, 0., 0.), Point3::new(0., 0.5, 0.), Point3::new(5., 5., 5.)];
+        mesh.establish_connections(0.75);
+
+        let options = ObjExportOptions { connections: true };
+        assert_eq!(4, write_mesh_obj(&test_path, &mesh, &options, &[[0, 1, 2]]).expect("Writing failed"));
+        let data = obj::read_obj_data(&test_path).unwrap();
+        assert_eq!(vec![[0., 0., 0.], [0.5, 0., 0.], [0., 0.5, 0.], [5., 5., 5.]], data.vertices);
+        assert_eq!(vec![[0, 1], [0, 2], [1, 2]], data.lines);
+        assert_eq!(vec![vec![0, 1, 2]], data.faces);
+
+        write_mesh_obj(&test_path, &mesh, &ObjExportOptions::default(), &[]).unwrap();
+        assert!(obj::read_obj_data(&test_path).unwrap().lines.is_empty());
+
+        assert!(write_mesh_obj(&test_path, &mesh, &options, &[[0, 1, 4]]).is_err());
+    }
+}
@@ -1,18 +1,67 @@
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::sync::OnceLock;
 use nalgebra::Point3;
 type Point3D = nalgebra::Point3<f64>;
+use super::mesh_io;
+use super::spatial_index::KdTree;
+use super::connection_graph::ConnectionGraph;
+use super::obj_export::{self, ObjExportOptions};
 
+use std::path::Path;
+use ps_data_layer::{Result, WriteOptions};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BreakingPoint {
     Broken, 
     Intact(f64),
 }
 
+// Properties of the connection between two points, for fracture and mass-spring simulations.
+// Damage accumulates from 0 (intact) to 1, where the bond breaks and leaves the graph.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bond {
+    pub rest_length: f64,
+    pub stiffness: f64,
+    pub damage: f64,
+    // Materials of the two points, lower point first
+    pub material_pair: [u16; 2],
+}
+
+impl Bond {
+    pub fn new(rest_length: f64) -> Self {
+        Self { rest_length, stiffness: 1., damage: 0., material_pair: [0, 0] }
+    }
+}
+
+// How establish_connections picks the neighbours of each point.
+// The k nearest relations are made symmetric: Knn keeps a pair when either point is
+// among the k nearest of the other, MutualKnn only when both are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConnectionMode {
+    Radius(f64),
+    Knn(usize),
+    MutualKnn(usize),
+    KnnWithinRadius { k: usize, radius: f64 },
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PointsMesh {
     pub points: Vec<Point3<f64>>,
     pub energies: Vec<f64>,
     pub breaking_point: Vec<BreakingPoint>,
-    pub connections: HashMap<usize, Vec<usize>>,
+    pub connections: ConnectionGraph<Bond>,
+    // Built on the first query, for points_within() and nearest_points(). Code moving,
+    // adding or removing points directly must call invalidate_spatial_index().
+    #[cfg_attr(feature = "serde", serde(skip))]
+    spatial_index: OnceLock<KdTree>,
+}
+
+
+impl Default for PointsMesh {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 
@@ -22,76 +71,108 @@ impl PointsMesh {
             points: Vec::new(),
             energies: Vec::new(),
             breaking_point: Vec::new(),
-            connections: HashMap::new(),
+            connections: ConnectionGraph::new(0),
+            spatial_index: OnceLock::new(),
         }
     }
     
-    pub fn create_points(&mut self, points: Vec<Point3D>) {
+    // Complete simulation state, see mesh_io.rs for the file layout.
+    pub fn new_from_file(path: &Path) -> Result<Self> {
+        mesh_io::read_mesh(path)
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> Result<usize> {
+        mesh_io::write_mesh(path, self, &WriteOptions::default())
+    }
+
+    // Points as OBJ vertices, see obj_export.rs. No faces are written: a reconstructed
+    // surface has its own vertices, export it with TriangleMesh::write_obj().
+    pub fn write_obj(&self, path: &Path, options: &ObjExportOptions) -> Result<usize> {
+        obj_export::write_mesh_obj(path, self, options, &[])
+    }
+
+    pub fn create_points(&mut self, _points: Vec<Point3D>) {
 
     }
 
 
+    // Connects the pairs of points closer than the radius.
     pub fn establish_connections(&mut self, radius: f64) {
-        self.connections.clear();
+        self.connect(ConnectionMode::Radius(radius));
+    }
+
+    // Rebuilds the connections with a spatial index, see spatial_index.rs. The index is
+    // built for the call, so the connections always follow the current positions.
+    // Each bond starts intact, at rest at the current distance of its points.
+    pub fn connect(&mut self, mode: ConnectionMode) {
+        let tree = KdTree::new(&self.points);
+        let mut pairs = Vec::new();
+
+        let (k, radius) = match mode {
+            ConnectionMode::Radius(radius) => {
+                for (i, point) in self.points.iter().enumerate() {
+                    pairs.extend(tree.within_radius(point, radius).into_iter().filter(|&j| j > i).map(|j| (i, j)));
+                }
+                self.connections = bonds_between(&self.points, pairs);
+                return;
+            }
+            ConnectionMode::Knn(k) | ConnectionMode::MutualKnn(k) => (k, f64::INFINITY),
+            ConnectionMode::KnnWithinRadius { k, radius } => (k, radius),
+        };
 
-        for i in 0..self.points.len() {
-            for j in (i + 1)..self.points.len() {
-                let distance = nalgebra::distance(&self.points[i], &self.points[j]);
+        // One more, as each point finds itself.
+        let nearest: Vec<Vec<usize>> = self.points.iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let mut neighbours: Vec<usize> = tree.nearest(point, k + 1).into_iter()
+                    .filter(|&(j, distance)| j != i && distance <= radius)
+                    .map(|(j, _)| j)
+                    .collect();
+                neighbours.truncate(k);
+                neighbours.sort_unstable();
+                neighbours
+            })
+            .collect();
 
-                if distance <= radius {
-                    self.connections.entry(i).or_insert_with(Vec::new).push(j);
-                    self.connections.entry(j).or_insert_with(Vec::new).push(i);
+        // Pairs found from both ends are kept once.
+        let mutual = matches!(mode, ConnectionMode::MutualKnn(_));
+        for (i, neighbours) in nearest.iter().enumerate() {
+            for &j in neighbours {
+                let reverse = nearest[j].binary_search(&i).is_ok();
+                if (mutual && !reverse) || (reverse && j < i) {
+                    continue;
                 }
+                pairs.push((i, j));
             }
         }
+        self.connections = bonds_between(&self.points, pairs);
     }
 
     pub fn remove_unconnected_points(&mut self) {
-        let unconnected_points: HashSet<usize> = self.points
-            .iter()
-            .enumerate()
-            .filter(|(index, _point)| !self.connections.contains_key(index))
-            .map(|(index, _point)| index)
+        let connected: Vec<bool> = (0..self.points.len())
+            .map(|index| self.get_point_connections(index).is_some())
             .collect();
-    
-        let mut new_points = Vec::new();
-        let mut index_mapping = HashMap::new();
-        let mut new_index = 0;
-    
-        for (old_index, point) in self.points.iter().enumerate() {
-            if !unconnected_points.contains(&old_index) {
-                new_points.push(point.clone());
-                index_mapping.insert(old_index, new_index);
-                new_index += 1;
-            }
+
+        self.connections.retain_points(|index| connected.get(index) == Some(&true));
+        // Energies and breaking points follow the points when there is one per point.
+        if self.energies.len() == connected.len() {
+            retain_by_mask(&mut self.energies, &connected);
         }
-    
-        self.points = new_points;
-    
-        let mut new_connections = HashMap::new();
-    
-        for (old_key, indices) in self.connections.iter() {
-            let new_key = *index_mapping.get(old_key).unwrap();
-    
-            let new_indices: Vec<usize> = indices
-                .iter()
-                .map(|old_index| *index_mapping.get(old_index).unwrap())
-                .collect();
-    
-            new_connections.insert(new_key, new_indices);
+        if self.breaking_point.len() == connected.len() {
+            retain_by_mask(&mut self.breaking_point, &connected);
         }
-    
-        self.connections = new_connections;
+        retain_by_mask(&mut self.points, &connected);
+        self.invalidate_spatial_index();
     }
 
     pub fn average_connections_per_point(&self) -> f64 {
-        let total_connections: usize = self.connections.values().map(|v| v.len()).sum();
+        let total_connections = 2 * self.connections.edge_count();
         total_connections as f64 / self.points.len() as f64
     }
 
     pub fn get_statistics(&self) -> (f64, usize) {
         let average_connections = self.average_connections_per_point();
-        let unconnected_points = self.points.len() - self.connections.len();
+        let unconnected_points = (0..self.points.len()).filter(|&index| self.get_point_connections(index).is_none()).count();
 
         (average_connections, unconnected_points)
     }
@@ -104,7 +185,7 @@ impl PointsMesh {
             let mut count = 0;
             let mut sum = Point3D::new(0.0, 0.0, 0.0);
     
-            for neighbor_index in self.connections.get(&index).unwrap_or(&vec![]).iter() {
+            for neighbor_index in self.get_point_connections(index).unwrap_or(&[]) {
                 let neighbor = &self.points[*neighbor_index];
                 sum.x += neighbor.x;
                 sum.y += neighbor.y;
@@ -113,18 +194,19 @@ impl PointsMesh {
             }
     
             if count > 0 {
-                let mut current_point = self.points[index].clone();
+                let mut current_point = self.points[index];
                 current_point.x = current_point.x * (1.0 - relaxation_factor) + (sum.x / count as f64) * relaxation_factor;
                 current_point.y = current_point.y * (1.0 - relaxation_factor) + (sum.y / count as f64) * relaxation_factor;
                 current_point.z = current_point.z * (1.0 - relaxation_factor) + (sum.z / count as f64) * relaxation_factor;
                 new_positions.push(current_point);
             } else {
-                new_positions.push(self.points[index].clone());
+                new_positions.push(self.points[index]);
             }
         }
     
         // After finding the new positions, replacing the old positions of the points.
         self.points = new_positions;
+        self.invalidate_spatial_index();
     }
 
 
@@ -133,7 +215,209 @@ impl PointsMesh {
     }
 
 
-    pub fn get_point_connections(&self, index: usize) -> Option<&Vec<usize>> {
-        self.connections.get(&index)
+    // None for unconnected points, see ConnectionGraph::neighbours() for the slice alone.
+    pub fn get_point_connections(&self, index: usize) -> Option<&[usize]> {
+        if index >= self.connections.point_count() || self.connections.degree(index) == 0 {
+            return None;
+        }
+        Some(self.connections.neighbours(index))
+    }
+
+
+    // Bonds, between connected points only.
+
+    pub fn bond(&self, a: usize, b: usize) -> Option<&Bond> {
+        self.connections.edge(a, b)
+    }
+
+    pub fn bond_mut(&mut self, a: usize, b: usize) -> Option<&mut Bond> {
+        self.connections.edge_mut(a, b)
+    }
+
+    // Each bond once, as (a, b, bond) with a < b.
+    pub fn bonds(&self) -> impl Iterator<Item = (usize, usize, &Bond)> + '_ {
+        self.connections.edges()
+    }
+
+    // Removes the connection, returning its last state.
+    pub fn break_bond(&mut self, a: usize, b: usize) -> Option<Bond> {
+        self.connections.remove_edge(a, b)
+    }
+
+    // Adds damage to the bond, breaking it when the damage reaches 1.
+    // Returns whether the bond broke, None without a bond between the points or for
+    // a negative or non-finite amount: damage never heals.
+    pub fn damage_bond(&mut self, a: usize, b: usize, amount: f64) -> Option<bool> {
+        if !amount.is_finite() || amount < 0. {
+            return None;
+        }
+        let bond = self.connections.edge_mut(a, b)?;
+        bond.damage = (bond.damage + amount).min(1.);
+        if bond.damage < 1. {
+            return Some(false);
+        }
+        self.connections.remove_edge(a, b);
+        Some(true)
+    }
+
+
+    // Neighbourhood queries, at any position. See spatial_index.rs.
+
+    // Indices of the points at distance <= radius, in increasing order.
+    pub fn points_within(&self, center: &Point3D, radius: f64) -> Vec<usize> {
+        self.spatial_index().within_radius(center, radius)
+    }
+
+    // The k nearest points with their distance, closest first.
+    pub fn nearest_points(&self, center: &Point3D, k: usize) -> Vec<(usize, f64)> {
+        self.spatial_index().nearest(center, k)
+    }
+
+    pub fn spatial_index(&self) -> &KdTree {
+        self.spatial_index.get_or_init(|| KdTree::new(&self.points))
+    }
+
+    // Rebuilt lazily, on the next query.
+    pub fn invalidate_spatial_index(&mut self) {
+        self.spatial_index = OnceLock::new();
+    }
+}
+
+
+fn bonds_between(points: &[Point3D], pairs: Vec<(usize, usize)>) -> ConnectionGraph<Bond> {
+    let edges = pairs.into_iter().map(|(a, b)| (a, b, Bond::new(nalgebra::distance(&points[a], &points[b]))));
+    ConnectionGraph::from_edges(points.len(), edges).expect("Neighbour out of the points")
+}
+
+fn retain_by_mask<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut index = 0;
+    values.retain(|_| {
+        index += 1;
+        keep[index - 1]
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected_pairs(mesh: &PointsMesh) -> Vec<(usize, usize)> {
+        mesh.bonds().map(|(a, b, _)| (a, b)).collect()
+    }
+
+    #[test]
+    fn establish_connections_test() {
+        // Grid with the spacing equal to the radius, plus a duplicate point.
+        let mut mesh = PointsMesh::new();
+        for i in 0..1000 {
+            mesh.points.push(Point3::new((i % 10) as f64 * 0.1, (i / 10 % 10) as f64 * 0.1, (i / 100) as f64 * 0.1));
+        }
+        mesh.points.push(Point3::new(0.3, 0.3, 0.3));
+        mesh.establish_connections(0.1);
+
+        let mut pairs = Vec::new();
+        for i in 0..mesh.points.len() {
+            for j in (i + 1)..mesh.points.len() {
+                if nalgebra::distance(&mesh.points[i], &mesh.points[j]) <= 0.1 {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        assert_eq!(pairs, connected_pairs(&mesh));
+    }
+
+    #[test]
+    fn knn_connections_test() {
+        // A dense cluster and two far away points, lost with a radius.
+        let mut mesh = PointsMesh::new();
+        mesh.points = vec![
+            Point3::new(0., 0., 0.), Point3::new(0.1, 0., 0.), Point3::new(0., 0.1, 0.), Point3::new(0., 0., 0.1),
+            Point3::new(10., 0., 0.), Point3::new(30., 0., 0.),
+        ];
+        mesh.connect(ConnectionMode::Radius(0.5));
+        assert_eq!((6, 2), (mesh.connections.edge_count(), mesh.get_statistics().1));
+
+        mesh.connect(ConnectionMode::Knn(1));
+        assert_eq!(vec![(0, 1), (0, 2), (0, 3), (1, 4), (4, 5)], connected_pairs(&mesh));
+
+        mesh.connect(ConnectionMode::MutualKnn(1));
+        assert_eq!(vec![(0, 1)], connected_pairs(&mesh));
+
+        mesh.connect(ConnectionMode::KnnWithinRadius { k: 2, radius: 15. });
+        assert_eq!(Some(&[1, 2, 3, 4][..]), mesh.get_point_connections(0));
+        assert_eq!(Some(&[0, 1][..]), mesh.get_point_connections(4));
+        assert_eq!(None, mesh.get_point_connections(5));
+    }
+
+    #[test]
+    fn queries_test() {
+        let mut mesh = PointsMesh::new();
+        mesh.points = vec![Point3::new(0., 0., 0.), Point3::new(1., 0., 0.), Point3::new(3., 0., 0.)];
+        assert_eq!(vec![0, 1], mesh.points_within(&Point3::new(0.5, 0.5, 0.), 1.));
+        assert_eq!(vec![(2, 0.5), (1, 1.5)], mesh.nearest_points(&Point3::new(2.5, 0., 0.), 2));
+
+        // Relaxation pulls the points together, the index follows.
+        mesh.establish_connections(2.);
+        mesh.relaxation_step(0.5);
+        assert_eq!(Point3::new(0.5, 0., 0.), mesh.points[0]);
+        assert_eq!(vec![0], mesh.points_within(&Point3::new(0., 0., 0.), 1.));
+        assert_eq!(vec![(2, 0.)], mesh.nearest_points(&Point3::new(2., 0., 0.), 1));
+
+        mesh.remove_unconnected_points();
+        mesh.points.push(Point3::new(0., 0., 0.));
+        mesh.invalidate_spatial_index();
+        assert_eq!(vec![(3, 0.)], mesh.nearest_points(&Point3::new(0., 0., 0.), 1));
+
+        // Connections follow points moved or removed without invalidating the index.
+        mesh.points[0] = Point3::new(10., 0., 0.);
+        mesh.establish_connections(1.);
+        assert_eq!(Some(&[2][..]), mesh.get_point_connections(1));
+        assert_eq!(None, mesh.get_point_connections(0));
+        mesh.points.truncate(2);
+        mesh.establish_connections(20.);
+        assert_eq!(1, mesh.connections.edge_count());
+    }
+
+    #[test]
+    fn bonds_test() {
+        let mut mesh = PointsMesh::new();
+        mesh.points = vec![Point3::new(0., 0., 0.), Point3::new(0.5, 0., 0.), Point3::new(0.5, 0.5, 0.)];
+        mesh.establish_connections(0.6);
+        assert_eq!(Some(&Bond::new(0.5)), mesh.bond(1, 0));
+        assert_eq!(None, mesh.bond(0, 2));
+
+        let bond = mesh.bond_mut(1, 2).unwrap();
+        bond.stiffness = 20.;
+        bond.material_pair = [1, 2];
+        assert_eq!(Some(false), mesh.damage_bond(2, 1, 0.6));
+        assert_eq!(None, mesh.damage_bond(2, 1, -0.5));
+        assert_eq!(None, mesh.damage_bond(2, 1, f64::NAN));
+        assert_eq!(Some(&Bond { rest_length: 0.5, stiffness: 20., damage: 0.6, material_pair: [1, 2] }), mesh.bond(1, 2));
+        assert_eq!(Some(true), mesh.damage_bond(1, 2, 0.6));
+        assert_eq!(None, mesh.bond(1, 2));
+        assert_eq!(None, mesh.get_point_connections(2));
+
+        assert_eq!(Some(Bond::new(0.5)), mesh.break_bond(0, 1));
+        assert_eq!(None, mesh.damage_bond(0, 1, 1.));
+        assert_eq!(0, mesh.connections.edge_count());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        let mut mesh = PointsMesh::new();
+        mesh.points = vec![Point3::new(0., 0., 0.), Point3::new(0.5, 0., 0.)];
+        mesh.energies = vec![1., 2.];
+        mesh.breaking_point = vec![BreakingPoint::Broken, BreakingPoint::Intact(0.25)];
+        mesh.establish_connections(1.);
+
+        let json = serde_json::to_string(&mesh).unwrap();
+        assert!(json.starts_with(r#"{"points":[[0.0,0.0,0.0],[0.5,0.0,0.0]]"#), "Unexpected {}", json);
+
+        let read_mesh: PointsMesh = serde_json::from_str(&json).unwrap();
+        assert_eq!(mesh.points, read_mesh.points);
+        assert_eq!(mesh.breaking_point, read_mesh.breaking_point);
+        assert_eq!(mesh.connections, read_mesh.connections);
     }
 }
@@ -5,6 +5,7 @@ use super::point_mesh::PointsMesh;
 // Stub function for creating a cube
 pub fn create_cube(side: f32, step: f32, mesh : &mut PointsMesh) {
     mesh.points.clear();
+    mesh.invalidate_spatial_index();
     println!("Creating a cube with side {} and step {}", side, step);
     let side_elements = (side / step) as usize;
     
@@ -25,6 +26,7 @@ pub fn create_cube(side: f32, step: f32, mesh : &mut PointsMesh) {
 
 pub fn create_sphere(radius: f32, step: f32, mesh : &mut PointsMesh) {
     mesh.points.clear();
+    mesh.invalidate_spatial_index();
     println!("Creating a circle with radius {} and step {}", radius, step);
     let side_elements = (radius * 2. / step) as usize;
     
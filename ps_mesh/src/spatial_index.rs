@@ -0,0 +1,229 @@
+// k-d tree over the points of a mesh, for neighbourhood queries.
+// The tree is implicit: the point indices are arranged so that, in each range, the
+// middle element is the node splitting the range on its axis (the largest extent of
+// the range), with the smaller coordinates before it and the larger ones after.
+// Small ranges are scanned linearly.
+// The tree keeps a copy of the points: it must be rebuilt when they move.
+
+use nalgebra::Point3;
+
+use std::collections::BinaryHeap;
+use std::cmp::Ordering;
+
+const LEAF_SIZE: usize = 8;
+
+
+#[derive(Debug, Clone)]
+pub struct KdTree {
+    points: Vec<Point3<f64>>,
+    indices: Vec<usize>,
+    // Split axis of the node at the same position in indices
+    axes: Vec<u8>,
+}
+
+
+impl KdTree {
+    pub fn new(points: &[Point3<f64>]) -> Self {
+        let mut tree = KdTree {
+            points: points.to_vec(),
+            indices: (0..points.len()).collect(),
+            axes: vec![0; points.len()],
+        };
+        tree.build(0, points.len());
+        tree
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    fn build(&mut self, start: usize, end: usize) {
+        if end - start <= LEAF_SIZE {
+            return;
+        }
+
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+        for &index in &self.indices[start..end] {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(self.points[index][axis]);
+                max[axis] = max[axis].max(self.points[index][axis]);
+            }
+        }
+        let axis = (0..3).max_by(|&a, &b| (max[a] - min[a]).total_cmp(&(max[b] - min[b]))).unwrap_or(0);
+
+        let middle = (start + end) / 2;
+        let points = &self.points;
+        self.indices[start..end].select_nth_unstable_by(middle - start, |&a, &b| points[a][axis].total_cmp(&points[b][axis]));
+        self.axes[middle] = axis as u8;
+
+        self.build(start, middle);
+        self.build(middle + 1, end);
+    }
+
+    // Indices of the points at distance <= radius from the center, in increasing order.
+    pub fn within_radius(&self, center: &Point3<f64>, radius: f64) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.search_radius(center, radius, 0, self.points.len(), &mut found);
+        found.sort_unstable();
+        found
+    }
+
+    fn search_radius(&self, center: &Point3<f64>, radius: f64, start: usize, end: usize, found: &mut Vec<usize>) {
+        if end - start <= LEAF_SIZE {
+            found.extend(self.indices[start..end].iter()
+                .filter(|&&index| nalgebra::distance(center, &self.points[index]) <= radius));
+            return;
+        }
+
+        let middle = (start + end) / 2;
+        let index = self.indices[middle];
+        let axis = self.axes[middle] as usize;
+        if nalgebra::distance(center, &self.points[index]) <= radius {
+            found.push(index);
+        }
+
+        // The distance is never smaller than the gap along one axis. A NaN coordinate gives no bound.
+        let gap = center[axis] - self.points[index][axis];
+        if gap <= radius || gap.is_nan() {
+            self.search_radius(center, radius, start, middle, found);
+        }
+        if -gap <= radius || gap.is_nan() {
+            self.search_radius(center, radius, middle + 1, end, found);
+        }
+    }
+
+    // The k nearest points with their distance, closest first; ties go to the lower index.
+    pub fn nearest(&self, center: &Point3<f64>, k: usize) -> Vec<(usize, f64)> {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        if k > 0 {
+            self.search_nearest(center, k, 0, self.points.len(), &mut heap);
+        }
+        heap.into_sorted_vec().into_iter().map(|candidate| (candidate.index, candidate.distance)).collect()
+    }
+
+    fn search_nearest(&self, center: &Point3<f64>, k: usize, start: usize, end: usize, heap: &mut BinaryHeap<Candidate>) {
+        let mut consider = |index: usize| {
+            // Points with NaN coordinates come last.
+            let distance = nalgebra::distance(center, &self.points[index]);
+            let candidate = Candidate { distance: if distance.is_nan() { f64::INFINITY } else { distance }, index };
+            if heap.len() < k {
+                heap.push(candidate);
+            } else if heap.peek().is_some_and(|worst| candidate < *worst) {
+                heap.pop();
+                heap.push(candidate);
+            }
+        };
+
+        if end - start <= LEAF_SIZE {
+            self.indices[start..end].iter().for_each(|&index| consider(index));
+            return;
+        }
+
+        let middle = (start + end) / 2;
+        let index = self.indices[middle];
+        let axis = self.axes[middle] as usize;
+        consider(index);
+
+        // Closer side first, the other one only if it can still hold a better candidate.
+        let gap = center[axis] - self.points[index][axis];
+        let (near, far) = match gap <= 0. {
+            true => ((start, middle), (middle + 1, end)),
+            false => ((middle + 1, end), (start, middle)),
+        };
+        self.search_nearest(center, k, near.0, near.1, heap);
+        // A NaN coordinate gives no bound.
+        if heap.len() < k || gap.is_nan() || heap.peek().is_some_and(|worst| gap.abs() <= worst.distance) {
+            self.search_nearest(center, k, far.0, far.1, heap);
+        }
+    }
+}
+
+
+// Ordered by distance then index, the heap keeps the worst candidate on top.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    distance: f64,
+    index: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance).then(self.index.cmp(&other.index))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn within_radius_test() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut points: Vec<Point3<f64>> = (0..2000)
+            .map(|_| Point3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(0.0..0.1)))
+            .collect();
+        // Duplicates and points exactly at the radius
+        points.extend([Point3::new(0., 0., 0.), Point3::new(0., 0., 0.), Point3::new(0.1, 0., 0.)]);
+
+        let tree = KdTree::new(&points);
+        assert_eq!(points.len(), tree.len());
+        for center in points.iter().step_by(37).chain([Point3::new(0., 0., 0.), Point3::new(5., 5., 5.)].iter()) {
+            for radius in [0., 0.05, 0.1, 0.5] {
+                let expected: Vec<usize> = (0..points.len())
+                    .filter(|&index| nalgebra::distance(center, &points[index]) <= radius)
+                    .collect();
+                assert_eq!(expected, tree.within_radius(center, radius));
+            }
+        }
+
+        assert!(KdTree::new(&[]).within_radius(&Point3::new(0., 0., 0.), 1.).is_empty());
+
+        // NaN coordinates sort last, so the splitting node here has one.
+        let mut points: Vec<Point3<f64>> = (0..4).map(|x| Point3::new(x as f64, 0., 0.)).collect();
+        points.extend([Point3::new(f64::NAN, 0., 0.); 5]);
+        assert_eq!(vec![0, 1], KdTree::new(&points).within_radius(&Point3::new(0.5, 0., 0.), 0.5));
+    }
+
+    #[test]
+    fn nearest_test() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut points: Vec<Point3<f64>> = (0..2000)
+            .map(|_| Point3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)))
+            .collect();
+        points.extend([Point3::new(0., 0., 0.), Point3::new(0., 0., 0.)]);
+
+        let tree = KdTree::new(&points);
+        for center in points.iter().step_by(41).chain([Point3::new(0., 0., 0.), Point3::new(5., 5., 5.)].iter()) {
+            let mut expected: Vec<(usize, f64)> = (0..points.len())
+                .map(|index| (index, nalgebra::distance(center, &points[index])))
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            for k in [0, 1, 6, 30] {
+                assert_eq!(expected[..k], tree.nearest(center, k)[..]);
+            }
+        }
+        assert_eq!(points.len(), tree.nearest(&Point3::new(0., 0., 0.), 5000).len());
+    }
+}
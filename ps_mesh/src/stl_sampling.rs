@@ -0,0 +1,261 @@
+use nalgebra::Point3;
+type Point3D = nalgebra::Point3<f64>;
+use super::point_mesh::PointsMesh;
+
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use std::fs::File;
+use std::path::Path;
+use std::io::Read;
+use ps_data_layer::{Error, Result};
+
+
+// Reads the triangles of an ASCII or binary STL file.
+pub fn read_stl(file_path: &Path) -> Result<Vec<[Point3D; 3]>> {
+    let mut bytes = Vec::new();
+    File::open(file_path)?.read_to_end(&mut bytes)?;
+
+    // Binary files are recognized by their size, as some of them start with "solid" too.
+    if bytes.len() >= 84 {
+        let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+        if bytes.len() == 84 + count * 50 {
+            return Ok(read_binary_stl(&bytes[84..], count));
+        }
+    }
+
+    if bytes.starts_with(b"solid") {
+        let text = String::from_utf8_lossy(&bytes);
+        return read_ascii_stl(&text);
+    }
+
+    Err(Error::Malformed("neither an ASCII nor a binary STL file".to_string()))
+}
+
+
+fn read_binary_stl(bytes: &[u8], count: usize) -> Vec<[Point3D; 3]> {
+    let value = |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as f64;
+    let vertex = |offset: usize| Point3::new(value(offset), value(offset + 4), value(offset + 8));
+
+    // Each record: normal, 3 vertices, attribute byte count.
+    (0..count)
+        .map(|index| index * 50)
+        .map(|record| [vertex(record + 12), vertex(record + 24), vertex(record + 36)])
+        .collect()
+}
+
+
+fn read_ascii_stl(text: &str) -> Result<Vec<[Point3D; 3]>> {
+    let mut triangles = Vec::new();
+    let mut vertices = Vec::with_capacity(3);
+
+    let mut tokens = text.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "vertex" => {
+                let mut coordinates = [0f64; 3];
+                for coordinate in coordinates.iter_mut() {
+                    let value = tokens.next().unwrap_or("");
+                    *coordinate = value.parse()
+                        .map_err(|_| Error::Malformed(format!("bad vertex coordinate {}", value)))?;
+                }
+                vertices.push(Point3::new(coordinates[0], coordinates[1], coordinates[2]));
+            }
+            "endfacet" => {
+                if vertices.len() != 3 {
+                    return Err(Error::Malformed(format!("facet with {} vertices", vertices.len())));
+                }
+                triangles.push([vertices[0], vertices[1], vertices[2]]);
+                vertices.clear();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(triangles)
+}
+
+
+fn triangle_area(triangle: &[Point3D; 3]) -> f64 {
+    (triangle[1] - triangle[0]).cross(&(triangle[2] - triangle[0])).norm() / 2.
+}
+
+
+// Area-weighted random sampling of the surface, with density in points per unit area.
+// The mesh is replaced by the sampled points.
+pub fn sample_surface(triangles: &[[Point3D; 3]], density: f64, seed: u64, mesh: &mut PointsMesh) -> Result<()> {
+    *mesh = PointsMesh::new();
+
+    // Cumulative areas, to pick triangles proportionally to their area.
+    let mut cumulative_areas = Vec::with_capacity(triangles.len());
+    let mut total_area = 0.;
+    for triangle in triangles {
+        total_area += triangle_area(triangle);
+        cumulative_areas.push(total_area);
+    }
+
+    if !total_area.is_finite() || total_area == 0. {
+        return Err(Error::InvalidInput(format!("cannot sample a surface of area {}", total_area)));
+    }
+
+    let points_count = (total_area * density).round() as usize;
+    println!("Sampling {} points on a surface of area {}", points_count, total_area);
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..points_count {
+        let target = rng.gen_range(0.0..total_area);
+        let index = cumulative_areas.partition_point(|&area| area <= target).min(triangles.len() - 1);
+        let [a, b, c] = triangles[index];
+
+        // Uniform barycentric coordinates.
+        let r1: f64 = rng.gen::<f64>().sqrt();
+        let r2: f64 = rng.gen();
+        let point = a.coords * (1. - r1) + b.coords * (r1 * (1. - r2)) + c.coords * (r1 * r2);
+        mesh.points.push(Point3D::from(point));
+    }
+    Ok(())
+}
+
+
+// Fills the inside of a closed mesh with a regular grid of the given step.
+// Each grid row along x casts a ray and keeps the points between pairs of crossings.
+// The mesh is replaced by the grid points.
+pub fn fill_volume(triangles: &[[Point3D; 3]], step: f64, mesh: &mut PointsMesh) -> Result<()> {
+    *mesh = PointsMesh::new();
+    if !step.is_finite() || step <= 0. {
+        return Err(Error::InvalidInput(format!("invalid grid step {}", step)));
+    }
+    if triangles.is_empty() {
+        return Ok(());
+    }
+
+    let mut min = triangles[0][0];
+    let mut max = triangles[0][0];
+    for vertex in triangles.iter().flatten() {
+        min = min.inf(vertex);
+        max = max.sup(vertex);
+    }
+    if !(max - min).iter().all(|extent| extent.is_finite()) {
+        return Err(Error::InvalidInput("mesh with non-finite vertices".to_string()));
+    }
+
+    // Rays slightly off the grid rows, so they do not run exactly through edges and vertices.
+    let jitter = (step * 1e-6 * std::f64::consts::SQRT_2, step * 1e-6 * std::f64::consts::PI);
+
+    let x_elements = ((max.x - min.x) / step) as usize + 1;
+    let y_elements = ((max.y - min.y) / step) as usize + 1;
+    let z_elements = ((max.z - min.z) / step) as usize + 1;
+    let mut open_rows = 0;
+    let mut crossings = Vec::new();
+
+    for y_coord in 0..y_elements {
+        for z_coord in 0..z_elements {
+            let y = min.y + y_coord as f64 * step;
+            let z = min.z + z_coord as f64 * step;
+
+            crossings.clear();
+            crossings.extend(triangles.iter().filter_map(|triangle| x_crossing(triangle, y + jitter.0, z + jitter.1)));
+            if crossings.len() % 2 != 0 {
+                open_rows += 1;
+                continue;
+            }
+            crossings.sort_by(|a, b| a.total_cmp(b));
+
+            for x_coord in 0..x_elements {
+                let x = min.x + x_coord as f64 * step;
+                let before = crossings.partition_point(|&crossing| crossing < x);
+                if before % 2 == 1 {
+                    mesh.points.push(Point3::new(x, y, z));
+                }
+            }
+        }
+    }
+
+    if open_rows > 0 {
+        println!("Warning: skipped {} rows crossing the mesh an odd number of times, is it closed?", open_rows);
+    }
+    println!("Filled the volume with {} points", mesh.points.len());
+    Ok(())
+}
+
+
+// X coordinate where the line parallel to x through (y, z) crosses the triangle, if it does.
+fn x_crossing(triangle: &[Point3D; 3], y: f64, z: f64) -> Option<f64> {
+    let [a, b, c] = triangle;
+
+    // Barycentric coordinates of (y, z) in the triangle projected on the yz plane.
+    let determinant = (b.y - a.y) * (c.z - a.z) - (c.y - a.y) * (b.z - a.z);
+    if determinant == 0. {
+        return None;
+    }
+    let u = ((y - a.y) * (c.z - a.z) - (c.y - a.y) * (z - a.z)) / determinant;
+    let v = ((b.y - a.y) * (z - a.z) - (y - a.y) * (b.z - a.z)) / determinant;
+    if u < 0. || v < 0. || u + v > 1. {
+        return None;
+    }
+
+    Some(a.x + u * (b.x - a.x) + v * (c.x - a.x))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit cube made of 12 triangles, as binary STL bytes.
+    fn cube_stl() -> Vec<u8> {
+        let corners = |index: usize| [(index & 1) as f32, ((index >> 1) & 1) as f32, ((index >> 2) & 1) as f32];
+        let faces = [
+            [0, 2, 1], [1, 2, 3], [4, 5, 6], [5, 7, 6],
+            [0, 1, 4], [1, 5, 4], [2, 6, 3], [3, 6, 7],
+            [0, 4, 2], [2, 4, 6], [1, 3, 5], [3, 7, 5]];
+
+        let mut bytes = vec![0u8; 80];
+        bytes.extend_from_slice(&(faces.len() as u32).to_le_bytes());
+        for face in faces {
+            bytes.extend_from_slice(&[0u8; 12]);
+            for corner in face {
+                for value in corners(corner) {
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            bytes.extend_from_slice(&[0u8; 2]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn stl_sampling_test() {
+        let test_path = std::env::temp_dir().join("ps_mesh_cube.stl");
+        std::fs::write(&test_path, cube_stl()).unwrap();
+        let triangles = read_stl(&test_path).expect("Reading failed");
+        assert_eq!(12, triangles.len());
+
+        // Surface: 6 unit faces at 100 points per unit area.
+        let mut mesh = PointsMesh::new();
+        mesh.energies = vec![1.; 3];
+        sample_surface(&triangles, 100., 42, &mut mesh).expect("Sampling failed");
+        assert_eq!(600, mesh.points.len());
+        assert!(mesh.energies.is_empty());
+        assert!(mesh.points.iter().all(|point| {
+            let on_face = |value: f64| value.abs() < 1e-9 || (value - 1.).abs() < 1e-9;
+            on_face(point.x) || on_face(point.y) || on_face(point.z)
+        }), "Sampled point not on the surface");
+
+        // Volume: a 0.25 grid, the boundary planes are on the crossings.
+        // The rays run just above the rows, so the y = 1 and z = 1 rows miss the cube, and
+        // x = 0 sits on the first crossing: 4 x 4 x 4 points.
+        fill_volume(&triangles, 0.25, &mut mesh).expect("Filling failed");
+        assert_eq!(64, mesh.points.len());
+        assert!(mesh.points.iter().all(|point| (0. ..=1.).contains(&point.x) && (0. ..=1.).contains(&point.y) && (0. ..=1.).contains(&point.z)));
+
+        let mut broken = triangles.clone();
+        broken[0][0].x = f64::NAN;
+        assert!(sample_surface(&broken, 100., 42, &mut mesh).is_err());
+        assert!(sample_surface(&[], 100., 42, &mut mesh).is_err());
+        broken[0][0].x = f64::INFINITY;
+        assert!(sample_surface(&broken, 100., 42, &mut mesh).is_err());
+        assert!(fill_volume(&broken, 0.25, &mut mesh).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+// Indexed triangle mesh, for surfaces extracted from the points, and its STL export.
+// Triangles are counter-clockwise seen from outside, so the normals point outwards.
+// validate() looks for the problems 3D printers and game engines refuse:
+//   - boundary edges, used by a single triangle: the mesh has holes
+//   - non-manifold edges, shared by more than two triangles
+//   - flipped edges, where two neighbour triangles have opposite orientations
+//   - degenerate triangles, with a repeated vertex or no area
+// write_stl() prints the report before writing, the file is written anyway.
+
+use nalgebra::Vector3;
+type Point3D = nalgebra::Point3<f64>;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::io::Write;
+
+use ps_data_layer::{atomic, Error, Result, WriteOptions};
+use ps_data_layer::obj::{self, ObjData};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TriangleMesh {
+    pub vertices: Vec<Point3D>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MeshReport {
+    pub boundary_edges: Vec<[usize; 2]>,
+    // Closed loops of boundary edges
+    pub holes: usize,
+    pub non_manifold_edges: Vec<[usize; 2]>,
+    pub flipped_edges: Vec<[usize; 2]>,
+    pub degenerate_triangles: Vec<usize>,
+}
+
+impl MeshReport {
+    // Closed, orientable and without degenerate triangles.
+    pub fn is_watertight(&self) -> bool {
+        self.boundary_edges.is_empty() && self.non_manifold_edges.is_empty()
+            && self.flipped_edges.is_empty() && self.degenerate_triangles.is_empty()
+    }
+}
+
+impl fmt::Display for MeshReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_watertight() {
+            return write!(f, "watertight mesh");
+        }
+        write!(f, "{} holes ({} boundary edges), {} non-manifold edges, {} flipped edges, {} degenerate triangles",
+            self.holes, self.boundary_edges.len(), self.non_manifold_edges.len(),
+            self.flipped_edges.len(), self.degenerate_triangles.len())
+    }
+}
+
+
+impl TriangleMesh {
+    pub fn new(vertices: Vec<Point3D>, triangles: Vec<[usize; 3]>) -> Result<Self> {
+        if let Some(triangle) = triangles.iter().find(|triangle| triangle.iter().any(|&index| index >= vertices.len())) {
+            return Err(Error::InvalidInput(format!("triangle {:?} out of {} vertices", triangle, vertices.len())));
+        }
+        Ok(Self { vertices, triangles })
+    }
+
+    // From a triangle soup, such as the content of an STL file: identical vertices are merged,
+    // so that neighbour triangles share their edges.
+    pub fn from_triangles(triangles: &[[Point3D; 3]]) -> Self {
+        let mut mesh = TriangleMesh::default();
+        let mut indices: HashMap<[u64; 3], usize> = HashMap::new();
+        for triangle in triangles {
+            let corners = triangle.map(|vertex| {
+                let key = [vertex.x, vertex.y, vertex.z].map(|value| (value + 0.).to_bits());
+                *indices.entry(key).or_insert_with(|| {
+                    mesh.vertices.push(vertex);
+                    mesh.vertices.len() - 1
+                })
+            });
+            mesh.triangles.push(corners);
+        }
+        mesh
+    }
+
+    pub fn triangle_points(&self, index: usize) -> [Point3D; 3] {
+        self.triangles[index].map(|vertex| self.vertices[vertex])
+    }
+
+    // Unit normal from the winding order, zero for degenerate triangles.
+    pub fn face_normal(&self, index: usize) -> Vector3<f64> {
+        let [a, b, c] = self.triangle_points(index);
+        (b - a).cross(&(c - a)).try_normalize(0.).unwrap_or_else(Vector3::zeros)
+    }
+
+    pub fn face_normals(&self) -> Vec<Vector3<f64>> {
+        (0..self.triangles.len()).map(|index| self.face_normal(index)).collect()
+    }
+
+    // Area-weighted average of the normals of the triangles around each vertex.
+    pub fn vertex_normals(&self) -> Vec<Vector3<f64>> {
+        let mut normals = vec![Vector3::zeros(); self.vertices.len()];
+        for (index, triangle) in self.triangles.iter().enumerate() {
+            let [a, b, c] = self.triangle_points(index);
+            // Twice the area, along the face normal
+            let weighted = (b - a).cross(&(c - a));
+            for &vertex in triangle {
+                normals[vertex] += weighted;
+            }
+        }
+        normals.iter().map(|normal| normal.try_normalize(0.).unwrap_or_else(Vector3::zeros)).collect()
+    }
+
+    pub fn validate(&self) -> MeshReport {
+        let mut report = MeshReport::default();
+
+        // Triangles using each undirected edge, with the direction they walk it in.
+        let mut edges: HashMap<[usize; 2], Vec<bool>> = HashMap::new();
+        for (index, &[a, b, c]) in self.triangles.iter().enumerate() {
+            if a == b || b == c || a == c || self.face_normal(index) == Vector3::zeros() {
+                report.degenerate_triangles.push(index);
+                continue;
+            }
+            for (from, to) in [(a, b), (b, c), (c, a)] {
+                edges.entry([from.min(to), from.max(to)]).or_default().push(from < to);
+            }
+        }
+
+        for (edge, directions) in &edges {
+            match directions.len() {
+                1 => report.boundary_edges.push(*edge),
+                2 if directions[0] == directions[1] => report.flipped_edges.push(*edge),
+                2 => {}
+                _ => report.non_manifold_edges.push(*edge),
+            }
+        }
+        report.boundary_edges.sort_unstable();
+        report.non_manifold_edges.sort_unstable();
+        report.flipped_edges.sort_unstable();
+        report.holes = count_loops(&report.boundary_edges);
+        report
+    }
+
+
+    // Writing
+
+    pub fn write_stl(&self, path: &Path, format: StlFormat) -> Result<usize> {
+        let report = self.validate();
+        if !report.is_watertight() {
+            println!("Warning: {} has {}", path.display(), report);
+        }
+
+        let normals = self.face_normals();
+        atomic::write_atomically(path, &WriteOptions::default(), |writer| {
+            match format {
+                StlFormat::Ascii => {
+                    writeln!(writer, "solid ps_mesh")?;
+                    for (index, normal) in normals.iter().enumerate() {
+                        writeln!(writer, "  facet normal {:e} {:e} {:e}", normal.x as f32, normal.y as f32, normal.z as f32)?;
+                        writeln!(writer, "    outer loop")?;
+                        for vertex in self.triangle_points(index) {
+                            writeln!(writer, "      vertex {:e} {:e} {:e}", vertex.x as f32, vertex.y as f32, vertex.z as f32)?;
+                        }
+                        writeln!(writer, "    endloop")?;
+                        writeln!(writer, "  endfacet")?;
+                    }
+                    writeln!(writer, "endsolid ps_mesh")?;
+                }
+                StlFormat::Binary => {
+                    // The header must not start with "solid", or readers take the file for ASCII.
+                    let mut header = [b' '; 80];
+                    header[..15].copy_from_slice(b"binary ps_mesh ");
+                    writer.write_all(&header)?;
+                    writer.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+                    for (index, normal) in normals.iter().enumerate() {
+                        let [a, b, c] = self.triangle_points(index);
+                        for value in [normal.x, normal.y, normal.z, a.x, a.y, a.z, b.x, b.y, b.z, c.x, c.y, c.z] {
+                            writer.write_all(&(value as f32).to_le_bytes())?;
+                        }
+                        writer.write_all(&0u16.to_le_bytes())?;
+                    }
+                }
+            }
+            Ok(())
+        })?;
+
+        println!("Written {} triangles to {}", self.triangles.len(), path.display());
+        Ok(self.triangles.len())
+    }
+
+    pub fn write_obj(&self, path: &Path) -> Result<usize> {
+        let data = ObjData {
+            vertices: self.vertices.iter().map(|vertex| [vertex.x, vertex.y, vertex.z]).collect(),
+            faces: self.triangles.iter().map(|triangle| triangle.to_vec()).collect(),
+            ..ObjData::default()
+        };
+        obj::write_obj(path, &data)?;
+        Ok(self.triangles.len())
+    }
+}
+
+
+// Connected groups of boundary edges, each one around a hole.
+fn count_loops(edges: &[[usize; 2]]) -> usize {
+    let mut parents: HashMap<usize, usize> = HashMap::new();
+    fn root(parents: &mut HashMap<usize, usize>, vertex: usize) -> usize {
+        let parent = *parents.entry(vertex).or_insert(vertex);
+        if parent == vertex {
+            return vertex;
+        }
+        let found = root(parents, parent);
+        parents.insert(vertex, found);
+        found
+    }
+
+    for &[a, b] in edges {
+        let (root_a, root_b) = (root(&mut parents, a), root(&mut parents, b));
+        parents.insert(root_a, root_b);
+    }
+    let vertices: Vec<usize> = parents.keys().copied().collect();
+    vertices.iter().filter(|&&vertex| root(&mut parents, vertex) == vertex).count()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stl_sampling::read_stl;
+    use nalgebra::Point3;
+
+    fn tetrahedron() -> TriangleMesh {
+        let vertices = vec![Point3::new(0., 0., 0.), Point3::new(1., 0., 0.), Point3::new(0., 1., 0.), Point3::new(0., 0., 1.)];
+        TriangleMesh::new(vertices, vec![[0, 2, 1], [0, 1, 3], [1, 2, 3], [0, 3, 2]]).unwrap()
+    }
+
+    #[test]
+    fn validation_test() {
+        let mut mesh = tetrahedron();
+        assert!(mesh.validate().is_watertight());
+        assert_eq!(Vector3::new(0., 0., -1.), mesh.face_normal(0));
+        let expected = Vector3::new(-1., -1., -1.).normalize();
+        assert!((mesh.vertex_normals()[0] - expected).norm() < 1e-12);
+
+        // A missing face leaves a hole of three boundary edges.
+        let removed = mesh.triangles.pop().unwrap();
+        let report = mesh.validate();
+        assert_eq!((1, vec![[0, 2], [0, 3], [2, 3]]), (report.holes, report.boundary_edges.clone()));
+
+        // Flipped back in, then a fin on edge 0-1.
+        mesh.triangles.push([removed[0], removed[2], removed[1]]);
+        mesh.vertices.push(Point3::new(0.5, -1., 0.));
+        mesh.triangles.push([0, 1, 4]);
+        mesh.triangles.push([1, 1, 4]);
+        let report = mesh.validate();
+        assert_eq!(vec![[0, 1]], report.non_manifold_edges);
+        assert_eq!(3, report.flipped_edges.len());
+        assert_eq!(vec![5], report.degenerate_triangles);
+        assert!(TriangleMesh::new(mesh.vertices.clone(), vec![[0, 1, 9]]).is_err());
+    }
+
+    #[test]
+    fn stl_round_trip_test() {
+        let mesh = tetrahedron();
+        for (format, name) in [(StlFormat::Ascii, "ps_mesh_ascii.stl"), (StlFormat::Binary, "ps_mesh_binary.stl")] {
+            let test_path = std::env::temp_dir().join(name);
+            assert_eq!(4, mesh.write_stl(&test_path, format).expect("Writing failed"));
+            let read_mesh = TriangleMesh::from_triangles(&read_stl(&test_path).expect("Reading failed"));
+            assert_eq!(4, read_mesh.vertices.len());
+            for index in 0..4 {
+                assert_eq!(mesh.triangle_points(index), read_mesh.triangle_points(index));
+            }
+            assert!(read_mesh.validate().is_watertight());
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use ps_data_layer::PointCloud;
+use ps_data_layer::{AttributeData, AttributeKind};
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::time::Duration;
 
@@ -11,43 +12,67 @@ use kiss3d::nalgebra::Point3;
 // Polling on the target file
 use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
 
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+#[command(name = "ps_visualizer")]
+#[command(about = "Shows the working points file, reloading it when it changes")]
+struct VisualizerArguments {
+    // Points file to watch, overriding PS_POINTS_FILE and ps_config.toml
+    #[arg(long)]
+    points_file: Option<PathBuf>,
+}
+
 
 fn main() {
+    let arguments = VisualizerArguments::parse();
+    let working_file = match ps_data_layer::config::resolve_points_file(arguments.points_file.as_deref()) {
+        Ok(working_file) => working_file,
+        Err(e) => {
+            eprintln!("Error: cannot resolve the working file: {}", e);
+            return;
+        }
+    };
+    println!("Watching {} (from {:?})", working_file.path.display(), working_file.source);
+
     // Set up Kiss3D window
     let mut window = Window::new("3D Visualizer");
 
     // Set up the 3D rendering components (camera, lights, etc.)
     window.set_light(Light::StickToCamera);
     let mut point_cloud = PointCloud::new(Vec::<Point3<f32>>::new());
-    let target_path = point_cloud.get_standard_file();
-    let target_path = Path::new(target_path);
+    let target_path = working_file.path.as_path();
 
     // Rendering once before starting the loop:
-    point_cloud.read_from_file(target_path.clone());
+    load_points(&mut point_cloud, target_path);
     draw_points (&point_cloud, &mut window);
 
     // Set up the notify file watcher
     let (tx, rx) = mpsc::channel();
     let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_secs(1)).unwrap();
 
+    // Watching the folder rather than the file: writers replace the file by renaming
+    // a temporary one over it, which a watch on the file itself would not follow.
+    let target_folder = match target_path.parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(target_folder).unwrap();
     watcher
-        .watch(target_path, RecursiveMode::NonRecursive)
+        .watch(target_folder, RecursiveMode::NonRecursive)
         .unwrap();
 
     while window.render() {
-            // Check for file changes
+            // Check for file changes, only complete files are moved into place
         match rx.try_recv() {
             Ok(DebouncedEvent::Create(path))
-            | Ok(DebouncedEvent::Write(path))
-            | Ok(DebouncedEvent::Rename(_, path)) => {
-                
+            | Ok(DebouncedEvent::Rename(_, path))
                 if ps_data_layer::are_paths_same(
                     &path,
-                    &Path::new(point_cloud.get_standard_file())) {
+                    target_path).unwrap_or(false) => {
 
-                    // Load the point cloud data from the binary file
-                    point_cloud.read_from_file(&path);
-                }
+                // Load the point cloud data from the binary file
+                load_points(&mut point_cloud, &path);
             }
             _ => {}
         }
@@ -58,11 +83,39 @@ fn main() {
 }
 
 
+fn load_points (i_cloud : &mut PointCloud, i_path : &Path) {
+    match i_cloud.read_from_file(i_path) {
+        Ok(_) => print_attributes(i_cloud),
+        Err(e) => eprintln!("Cannot read {}: {}", i_path.display(), e),
+    }
+}
+
+
+fn print_attributes (i_cloud : &PointCloud) {
+    println!("Loaded {} points", i_cloud.points.len());
+    for attribute in &i_cloud.attributes {
+        println!("  channel {} ({:?})", attribute.name, attribute.data.kind());
+    }
+}
+
+
 fn draw_points (i_cloud : &PointCloud, i_window : &mut Window) {
     
+    // Using the first color channel, if any.
+    let colors = match i_cloud.find_attribute(AttributeKind::Color).map(|attribute| &attribute.data) {
+        Some(AttributeData::Color(colors)) => Some(colors),
+        _ => None,
+    };
+
     // WARNING: nalgebra and kiss3D::nalgebra are separate objects, warning!""
-    for point in &i_cloud.points {
-        let color = kiss3d::nalgebra::geometry::Point3::<f32>::new(1.0, 1.0, 1.0);
+    for (index, point) in i_cloud.points.iter().enumerate() {
+        let color = match colors {
+            Some(colors) => {
+                let [r, g, b] = colors[index];
+                kiss3d::nalgebra::geometry::Point3::<f32>::new(r as f32 / 255., g as f32 / 255., b as f32 / 255.)
+            }
+            None => kiss3d::nalgebra::geometry::Point3::<f32>::new(1.0, 1.0, 1.0),
+        };
 
         // Temporarly converting nalgebra into kiss3d::nalgebra because somehow it's in conflict.
         let position = kiss3d::nalgebra::Point3::<f32>::new(point.x, point.y, point.z);